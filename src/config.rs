@@ -0,0 +1,291 @@
+use rand::Rng;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+pub const DEFAULT_DGG_URL: &str = "wss://chat.destiny.gg/ws";
+pub const DGG_AUTH_TOKEN_ENV: &str = "DGG_AUTH_TOKEN";
+pub const DEFAULT_KICK_CHANNEL: &str = "roshtein";
+pub const DEFAULT_KICK_API_URL: &str = "https://kick.com";
+pub const DEFAULT_KICK_PUSHER_URL: &str = "wss://ws-us2.pusher.com/app/eb1d5f283081a78b932c?protocol=7&client=js&version=7.6.0&flash=false";
+pub const DEFAULT_YOUTUBE_CHANNEL: &str = "destiny";
+pub const DEFAULT_TWITCH_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+pub const DEFAULT_RUMBLE_CHAT_URL: &str = "https://web7.rumble.com/chat/api/chat";
+
+/// The config file, a platform can be listed any number of times, for example:
+///
+/// ```toml
+/// [[sources]]
+/// platform = "dgg"
+///
+/// [[sources]]
+/// platform = "kick"
+/// channel = "xqc"
+///
+/// [[sources]]
+/// platform = "kick"
+/// channel = "roshtein"
+///
+/// [[sources]]
+/// platform = "youtube"
+/// channel = "destiny"
+///
+/// [[sources]]
+/// platform = "youtube"
+/// video = "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+///
+/// [[sources]]
+/// platform = "youtube"
+/// replay = "dQw4w9WgXcQ"
+/// proxy = "http://localhost:8080"
+///
+/// [[sources]]
+/// platform = "twitch"
+/// channel = "hasanabi"
+///
+/// [[sources]]
+/// platform = "rumble"
+/// stream = "https://rumble.com/v4n2b4j-live.html"
+///
+/// [[sources]]
+/// platform = "irc"
+/// server = "ircs://irc.libera.chat"
+/// network = "libera"
+/// channels = ["#rust", "#libera"]
+/// auth = { method = "sasl", password = "hunter2" }
+///
+/// [[sources]]
+/// platform = "websocket"
+/// name = "lobby"
+/// url = "wss://chat.example.com/ws"
+/// subscribe = ['{"type": "join", "room": "lobby"}']
+/// messages = "$.data.messages[*]"
+/// author = "$.user.name"
+/// text = "$.body"
+/// timestamp = "$.sent_at"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// every chat source listed here gets its own task
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "platform", rename_all = "lowercase")]
+pub enum SourceConfig {
+    Dgg {
+        /// the websocket url of the chat
+        #[serde(default = "default_dgg_url")]
+        url: String,
+        /// the dgg login token, needed to send messages,
+        /// falls back to the `DGG_AUTH_TOKEN` env var
+        #[serde(default = "default_dgg_auth_token")]
+        auth_token: Option<String>,
+    },
+    Kick {
+        /// the channel slug, for example: xqc
+        channel: String,
+        /// the kick api used to look up the chatroom id of the channel
+        #[serde(default = "default_kick_api_url")]
+        api_url: String,
+        /// the pusher websocket kick sends its chat events through
+        #[serde(default = "default_kick_pusher_url")]
+        pusher_url: String,
+    },
+    Twitch {
+        /// the channel login name, for example: hasanabi
+        channel: String,
+        /// the twitch irc websocket url
+        #[serde(default = "default_twitch_url")]
+        url: String,
+    },
+    Rumble {
+        /// the url of the video, its embed id like `v5c7u2x` or the numeric chat id
+        stream: String,
+        /// the chat api, the events are read from `{chat_url}/{chat id}/stream`
+        #[serde(default = "default_rumble_chat_url")]
+        chat_url: String,
+    },
+    Youtube {
+        #[serde(flatten)]
+        target: YoutubeTarget,
+        /// an http or https proxy url for all requests to youtube
+        #[serde(default)]
+        proxy: Option<String>,
+    },
+    Irc {
+        #[serde(flatten)]
+        network: IrcNetwork,
+    },
+    Websocket {
+        #[serde(flatten)]
+        chat: WebsocketChat,
+    },
+}
+
+/// Which youtube chat to follow
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum YoutubeTarget {
+    /// the live stream of a channel, the handle without the `@`, for example: destiny
+    Channel(String),
+    /// a video id or any watch, live or youtu.be url, for example: dQw4w9WgXcQ
+    Video(String),
+    /// the whole replay chat of a finished stream, a video id or url like `video`
+    Replay(String),
+}
+
+impl fmt::Display for YoutubeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YoutubeTarget::Channel(channel) => write!(f, "{}", channel),
+            YoutubeTarget::Video(video) => write!(f, "{}", video),
+            YoutubeTarget::Replay(video) => write!(f, "{} replay", video),
+        }
+    }
+}
+
+/// An irc network and the channels to join on it
+#[derive(Debug, Clone, Deserialize)]
+pub struct IrcNetwork {
+    /// `ircs://host:port` for tls or `irc://host:port` for plain tcp,
+    /// the port defaults to 6697 for tls and 6667 otherwise
+    pub server: String,
+    /// the name the chats are shown under, for example: libera, defaults to the host
+    #[serde(default)]
+    pub network: Option<String>,
+    #[serde(default = "default_irc_nick")]
+    pub nick: String,
+    /// the channels to join, the `#` can be left out
+    pub channels: Vec<String>,
+    /// how to log in, anonymous when not set
+    #[serde(default)]
+    pub auth: Option<IrcAuth>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum IrcAuth {
+    /// identify to NickServ once connected, as the configured nick
+    NickServ { password: String },
+    /// log in with SASL PLAIN before the connection is registered
+    Sasl {
+        /// the account name, defaults to the nick
+        #[serde(default)]
+        username: Option<String>,
+        password: String,
+    },
+}
+
+/// A chat that sends its messages as JSON over a websocket,
+/// the fields of a message are picked with JSONPath expressions
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebsocketChat {
+    /// the name the chat is shown under, for example: lobby
+    pub name: String,
+    /// the websocket url
+    pub url: String,
+    /// text frames sent once connected, for example to join a room
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+    /// picks the messages out of a frame, every match is one message,
+    /// the whole frame is the message when not set
+    #[serde(default)]
+    pub messages: Option<String>,
+    /// the author name, relative to the message
+    pub author: String,
+    /// the message text, relative to the message, frames without it are skipped
+    pub text: String,
+    /// when the message was sent, an RFC 3339 string or a unix timestamp in seconds or milliseconds,
+    /// the time it was received when not set
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    /// the id of the message
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+fn default_dgg_url() -> String {
+    DEFAULT_DGG_URL.to_string()
+}
+
+pub fn default_dgg_auth_token() -> Option<String> {
+    std::env::var(DGG_AUTH_TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+fn default_kick_api_url() -> String {
+    DEFAULT_KICK_API_URL.to_string()
+}
+
+fn default_kick_pusher_url() -> String {
+    DEFAULT_KICK_PUSHER_URL.to_string()
+}
+
+fn default_twitch_url() -> String {
+    DEFAULT_TWITCH_URL.to_string()
+}
+
+fn default_rumble_chat_url() -> String {
+    DEFAULT_RUMBLE_CHAT_URL.to_string()
+}
+
+/// default_irc_nick() is a random nick like `chatrs4821`, so several readers don't collide
+pub fn default_irc_nick() -> String {
+    format!("chatrs{}", rand::thread_rng().gen_range(1000..10000))
+}
+
+impl SourceConfig {
+    pub fn platform(&self) -> &'static str {
+        match self {
+            SourceConfig::Dgg { .. } => "dgg",
+            SourceConfig::Kick { .. } => "kick",
+            SourceConfig::Twitch { .. } => "twitch",
+            SourceConfig::Rumble { .. } => "rumble",
+            SourceConfig::Youtube { .. } => "youtube",
+            SourceConfig::Irc { .. } => "irc",
+            SourceConfig::Websocket { .. } => "websocket",
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            SourceConfig::Dgg { url, .. } => format!("dgg {}", url),
+            SourceConfig::Kick { channel, .. } => format!("kick {}", channel),
+            SourceConfig::Twitch { channel, .. } => format!("twitch {}", channel),
+            SourceConfig::Rumble { stream, .. } => format!("rumble {}", stream),
+            SourceConfig::Youtube { target, .. } => format!("youtube {}", target),
+            SourceConfig::Irc { network } => format!(
+                "irc {}",
+                network.network.as_deref().unwrap_or(&network.server)
+            ),
+            SourceConfig::Websocket { chat } => format!("websocket {}", chat.name),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let raw_config = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        toml::from_str(&raw_config).map_err(ConfigError::Toml)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "can't read config file, {}", err),
+            ConfigError::Toml(err) => write!(f, "can't parse config file, {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
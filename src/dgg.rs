@@ -1,35 +1,371 @@
-use crate::types::ChatMsg;
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{Author, Badge, ChatEvent, ChatLocation, ChatMsg, ChatNotice, NoticeKind, Role};
 
 use chrono::{DateTime, Utc};
-use futures_util::StreamExt;
-use log::{debug, info};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
 use serde::Deserialize;
-use tokio::sync::broadcast::Sender as tSender;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender as tSender;
+use tokio::sync::{mpsc, Mutex, MutexGuard};
 use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
 use url::Url;
 
-pub async fn main(tx: tSender<ChatMsg>) {
-    let url = Url::parse("wss://chat.destiny.gg/ws").unwrap();
+/// The receiving end of the messages a dgg chat should send,
+/// shared between reconnects so queued messages aren't lost
+pub type Outgoing = Arc<Mutex<mpsc::Receiver<String>>>;
 
-    info!("Connecting to dgg websocket");
-    let (mut socket, _) = connect_async(url).await.expect("Can't connect");
+/// A handle to send messages through a logged in dgg chat connection
+#[derive(Debug, Clone)]
+pub struct DggSender {
+    tx: mpsc::Sender<String>,
+}
+
+impl DggSender {
+    /// send_message() queues a chat message, it is sent as soon as the chat is connected
+    pub async fn send_message(&self, text: &str) -> Result<(), SourceError> {
+        self.tx
+            .send(text.to_string())
+            .await
+            .map_err(|_| SourceError::Other("dgg chat is not running".to_string()))
+    }
+}
+
+pub fn outgoing_channel() -> (DggSender, Outgoing) {
+    let (tx, rx) = mpsc::channel(16);
+    (DggSender { tx }, Arc::new(Mutex::new(rx)))
+}
+
+pub async fn main(
+    tx: tSender<ChatEvent>,
+    url: String,
+    auth_token: Option<String>,
+    outgoing: Option<Outgoing>,
+    health: Health,
+) {
+    let name = format!("dgg {}", url);
+    supervise(&name, &health, Backoff::default(), || {
+        connect(
+            tx.clone(),
+            url.clone(),
+            auth_token.clone(),
+            outgoing.clone(),
+        )
+    })
+    .await
+}
+
+async fn connect(
+    tx: tSender<ChatEvent>,
+    url: String,
+    auth_token: Option<String>,
+    outgoing: Option<Outgoing>,
+) -> Result<(), SourceError> {
+    let url = Url::parse(&url).map_err(|err| SourceError::Other(err.to_string()))?;
+    let channel = url.host_str().unwrap_or_default().to_string();
+
+    let mut request = url.as_str().into_client_request()?;
+    if let Some(auth_token) = &auth_token {
+        let cookie = format!("authtoken={}", auth_token)
+            .parse()
+            .map_err(|_| SourceError::Other("invalid dgg auth token".to_string()))?;
+        request.headers_mut().insert("Cookie", cookie);
+    }
+
+    info!(
+        "Connecting to dgg websocket {}{}",
+        url,
+        if auth_token.is_some() {
+            " logged in"
+        } else {
+            ""
+        }
+    );
+    let (mut socket, _) = connect_async(request).await?;
+    let mut outgoing = match &outgoing {
+        Some(outgoing) => Some(outgoing.lock().await),
+        None => None,
+    };
     info!("starting dgg msg loop");
+    let mut users = UserList::default();
+    let mut malformed = MalformedFrames::default();
     loop {
-        let msg = socket.next().await.unwrap().unwrap();
+        let msg = tokio::select! {
+            msg = socket.next() => msg.ok_or(SourceError::Closed)??,
+            Some(text) = next_outgoing(&mut outgoing) => {
+                socket.send(Message::Text(msg_frame(&text))).await?;
+                continue;
+            }
+        };
         if !msg.is_text() {
             continue;
         }
         let raw_msg_text = msg.to_string();
         debug!("`{}`", &raw_msg_text);
-        let raw_msg = RawDggMsg::from(raw_msg_text.as_str());
-        if raw_msg.m_type != "MSG" {
+
+        let event = match raw_msg_text.parse::<DggEvent>() {
+            Ok(event) => event,
+            Err(err) => {
+                malformed.record(&err);
+                warn!(
+                    "Can't parse dgg frame `{}`, {} ({} {} and {} malformed frames so far)",
+                    raw_msg_text,
+                    err,
+                    malformed.count(err.name()),
+                    err.name(),
+                    malformed.total()
+                );
+                continue;
+            }
+        };
+        users.apply(&event);
+
+        if let DggEvent::Ping(data) = &event {
+            let pong = match data {
+                Value::Null => "PONG".to_string(),
+                data => format!("PONG {}", data),
+            };
+            socket.send(Message::Text(pong)).await?;
             continue;
         }
 
-        let dgg_chat_msg: DggChatMsg = serde_json::from_str(raw_msg.m_content).unwrap();
-        let chat_msg = ChatMsg::from_dgg_msg(dgg_chat_msg, raw_msg_text);
+        if let Some(chat_event) = event.into_chat_event(&channel, &raw_msg_text) {
+            tx.send(chat_event).await?;
+        }
+    }
+}
+
+async fn next_outgoing(
+    outgoing: &mut Option<MutexGuard<'_, mpsc::Receiver<String>>>,
+) -> Option<String> {
+    match outgoing {
+        Some(outgoing) => outgoing.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// msg_frame() returns the frame that sends `text` as a chat message
+pub fn msg_frame(text: &str) -> String {
+    format!("MSG {}", serde_json::json!({ "data": text }))
+}
+
+/// The frames the dgg chat server sends, each is `TYPE {json}`
+#[derive(Debug)]
+pub enum DggEvent {
+    Msg(DggChatMsg),
+    Names(DggNames),
+    Join(DggUser),
+    Quit(DggUser),
+    Mute(DggModAction),
+    Ban(DggModAction),
+    Unban(DggModAction),
+    Broadcast(DggBroadcast),
+    PrivMsg(DggPrivMsg),
+    SubOnly(DggModAction),
+    PollStart(DggPoll),
+    PollStop(DggPoll),
+    Err(String),
+    /// a keepalive from the server, answered with `PONG` and the same data
+    Ping(Value),
+    /// a frame type we don't know about
+    Other(String),
+}
+
+impl FromStr for DggEvent {
+    type Err = DggParseError;
+
+    fn from_str(raw_msg_text: &str) -> Result<Self, DggParseError> {
+        let raw_msg = RawDggMsg::try_from(raw_msg_text)?;
+        DggEvent::parse(&raw_msg)
+    }
+}
+
+impl DggEvent {
+    pub fn parse(raw_msg: &RawDggMsg) -> Result<Self, DggParseError> {
+        let m_type = raw_msg.m_type;
+        let json = |err| DggParseError::Json {
+            m_type: m_type.to_string(),
+            err,
+        };
+        let content = raw_msg.m_content;
+        let event = match m_type {
+            "PING" => {
+                let data = if content.is_empty() {
+                    Value::Null
+                } else {
+                    serde_json::from_str(content).map_err(json)?
+                };
+                return Ok(DggEvent::Ping(data));
+            }
+            "MSG" | "NAMES" | "JOIN" | "QUIT" | "MUTE" | "BAN" | "UNBAN" | "BROADCAST"
+            | "PRIVMSG" | "SUBONLY" | "POLLSTART" | "POLLSTOP" | "ERR"
+                if content.is_empty() =>
+            {
+                return Err(DggParseError::MissingContent(m_type.to_string()));
+            }
+            "MSG" => DggEvent::Msg(serde_json::from_str(content).map_err(json)?),
+            "NAMES" => DggEvent::Names(serde_json::from_str(content).map_err(json)?),
+            "JOIN" => DggEvent::Join(serde_json::from_str(content).map_err(json)?),
+            "QUIT" => DggEvent::Quit(serde_json::from_str(content).map_err(json)?),
+            "MUTE" => DggEvent::Mute(serde_json::from_str(content).map_err(json)?),
+            "BAN" => DggEvent::Ban(serde_json::from_str(content).map_err(json)?),
+            "UNBAN" => DggEvent::Unban(serde_json::from_str(content).map_err(json)?),
+            "BROADCAST" => DggEvent::Broadcast(serde_json::from_str(content).map_err(json)?),
+            "PRIVMSG" => DggEvent::PrivMsg(serde_json::from_str(content).map_err(json)?),
+            "SUBONLY" => DggEvent::SubOnly(serde_json::from_str(content).map_err(json)?),
+            "POLLSTART" => DggEvent::PollStart(serde_json::from_str(content).map_err(json)?),
+            "POLLSTOP" => DggEvent::PollStop(serde_json::from_str(content).map_err(json)?),
+            "ERR" => {
+                // older servers send a bare JSON string, newer ones an object with a description
+                let err = match serde_json::from_str::<Value>(content).map_err(json)? {
+                    Value::String(err) => err,
+                    Value::Object(err) => err
+                        .get("description")
+                        .and_then(Value::as_str)
+                        .map(str::to_string)
+                        .unwrap_or(content.to_string()),
+                    err => err.to_string(),
+                };
+                DggEvent::Err(err)
+            }
+            m_type => DggEvent::Other(m_type.to_string()),
+        };
+        Ok(event)
+    }
+
+    /// into_chat_event() returns the event as it should be shown in the unified output,
+    /// `None` for events that only update state (like `JOIN` and `QUIT`)
+    pub fn into_chat_event(self, channel: &str, raw_msg_text: &str) -> Option<ChatEvent> {
+        let notice = |timestamp: Option<DateTime<Utc>>, kind: NoticeKind| {
+            ChatNotice {
+                location: ChatLocation::Dgg,
+                channel: channel.to_string(),
+                timestamp: timestamp.unwrap_or(Utc::now()),
+                stream_offset: None,
+                kind,
+                raw_full_msg: raw_msg_text.to_string(),
+            }
+            .into()
+        };
+
+        let chat_event = match self {
+            DggEvent::Msg(dgg_chat_msg) => {
+                ChatMsg::from_dgg_msg(dgg_chat_msg, channel.to_string(), raw_msg_text.to_string())
+                    .into()
+            }
+            DggEvent::Mute(action) => notice(
+                action.timestamp,
+                NoticeKind::UserMuted {
+                    expires_at: action.expires_at(),
+                    username: action.data,
+                    muted_by: Some(action.nick),
+                },
+            ),
+            DggEvent::Ban(action) => notice(
+                action.timestamp,
+                NoticeKind::UserBanned {
+                    expires_at: action.expires_at(),
+                    permanent: action.is_permanent(),
+                    username: action.data,
+                    banned_by: Some(action.nick),
+                },
+            ),
+            DggEvent::Unban(action) => notice(
+                action.timestamp,
+                NoticeKind::UserUnbanned {
+                    username: action.data,
+                    unbanned_by: Some(action.nick),
+                },
+            ),
+            DggEvent::SubOnly(action) => notice(
+                action.timestamp,
+                NoticeKind::SubOnly {
+                    enabled: action.data == "on",
+                    by: Some(action.nick),
+                },
+            ),
+            DggEvent::Broadcast(broadcast) => notice(
+                broadcast.timestamp,
+                NoticeKind::Broadcast {
+                    msg_text: broadcast.data,
+                },
+            ),
+            DggEvent::PrivMsg(priv_msg) => notice(
+                priv_msg.timestamp,
+                NoticeKind::Whisper {
+                    from: priv_msg.nick,
+                    msg_text: priv_msg.data,
+                },
+            ),
+            DggEvent::PollStart(poll) => notice(
+                None,
+                NoticeKind::PollStarted {
+                    question: poll.question,
+                    options: poll.options,
+                },
+            ),
+            DggEvent::PollStop(poll) => notice(
+                None,
+                NoticeKind::PollEnded {
+                    results: poll.options.into_iter().zip(poll.totals).collect(),
+                    question: poll.question,
+                },
+            ),
+            DggEvent::Err(err) => {
+                warn!("dgg chat error, {}", err);
+                return None;
+            }
+            DggEvent::Names(_) | DggEvent::Join(_) | DggEvent::Quit(_) | DggEvent::Ping(_) => {
+                return None
+            }
+            DggEvent::Other(m_type) => {
+                debug!("Ignoring dgg {} frame", m_type);
+                return None;
+            }
+        };
+        Some(chat_event)
+    }
+}
+
+/// The users currently connected to the chat, kept up to date from `NAMES`, `JOIN` and `QUIT`
+#[derive(Debug, Default)]
+pub struct UserList {
+    users: HashMap<String, DggUser>,
+    /// the server also counts connections of users that aren't logged in
+    connection_count: u64,
+}
 
-        tx.send(chat_msg).unwrap();
+impl UserList {
+    pub fn apply(&mut self, event: &DggEvent) {
+        match event {
+            DggEvent::Names(names) => {
+                self.connection_count = names.connectioncount;
+                self.users = names
+                    .users
+                    .iter()
+                    .map(|user| (user.nick.clone(), user.clone()))
+                    .collect();
+                info!(
+                    "dgg has {} users and {} connections",
+                    self.users.len(),
+                    self.connection_count
+                );
+            }
+            DggEvent::Join(user) => {
+                self.users.insert(user.nick.clone(), user.clone());
+            }
+            DggEvent::Quit(user) => {
+                self.users.remove(&user.nick);
+            }
+            _ => {}
+        }
     }
 }
 
@@ -38,7 +374,9 @@ pub async fn main(tx: tSender<ChatMsg>) {
 pub struct DggChatMsg {
     pub id: i64,
     pub nick: String,
+    #[serde(default)]
     pub roles: Vec<String>,
+    #[serde(default)]
     pub features: Vec<String>,
     #[serde(rename = "createdDate")]
     pub created_date: DateTime<Utc>,
@@ -48,6 +386,31 @@ pub struct DggChatMsg {
     pub watching: Option<Watching>,
 }
 
+impl DggChatMsg {
+    /// author() reads the roles from `features` and `roles`, every feature is also shown as a badge,
+    /// like `subscriber` or `flair3`
+    pub fn author(&self) -> Author {
+        let mut author = Author {
+            name: self.nick.clone(),
+            ..Author::default()
+        };
+        for name in self.features.iter().chain(&self.roles) {
+            if let Some(role) = Role::from_name(name) {
+                author.add_role(role);
+            }
+        }
+        author.badges = self
+            .features
+            .iter()
+            .map(|feature| Badge {
+                name: feature.clone(),
+                image_url: None,
+            })
+            .collect();
+        author
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, serde::Deserialize)]
 pub struct Watching {
@@ -55,15 +418,434 @@ pub struct Watching {
     pub id: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DggNames {
+    #[serde(default)]
+    pub connectioncount: u64,
+    #[serde(default)]
+    pub users: Vec<DggUser>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DggUser {
+    pub nick: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// `MUTE`, `BAN`, `UNBAN` and `SUBONLY`, `nick` is the moderator and `data` the target
+#[derive(Debug, Deserialize)]
+pub struct DggModAction {
+    pub nick: String,
+    pub data: String,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// in seconds, missing or 0 means the default mute or a permanent ban
+    pub duration: Option<i64>,
+}
+
+impl DggModAction {
+    /// is_permanent() is true for a ban without a duration
+    fn is_permanent(&self) -> bool {
+        self.expires_at().is_none()
+    }
+
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let duration = self.duration.filter(|duration| *duration > 0)?;
+        Some(self.timestamp.unwrap_or(Utc::now()) + chrono::Duration::seconds(duration))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DggBroadcast {
+    pub data: String,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DggPrivMsg {
+    pub nick: String,
+    pub data: String,
+    #[serde(default, with = "chrono::serde::ts_milliseconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DggPoll {
+    pub nick: Option<String>,
+    pub question: String,
+    #[serde(default)]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub totals: Vec<u64>,
+    #[serde(default)]
+    pub weighted: bool,
+    /// how long the poll runs in milliseconds
+    pub time: Option<u64>,
+}
+
+/// A frame split into its type and content, `PING` and other keyword only frames have no content
 #[derive(Debug)]
 pub struct RawDggMsg<'a> {
     pub m_type: &'a str,
     pub m_content: &'a str,
 }
-impl<'a> From<&'a str> for RawDggMsg<'a> {
-    fn from(value: &'a str) -> Self {
-        let (m_type, m_content) = value.split_once(' ').unwrap();
-        Self { m_type, m_content }
+
+impl<'a> TryFrom<&'a str> for RawDggMsg<'a> {
+    type Error = DggParseError;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(DggParseError::Empty);
+        }
+        let (m_type, m_content) = value.split_once(' ').unwrap_or((value, ""));
+        if !m_type.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(DggParseError::InvalidType(m_type.to_string()));
+        }
+        Ok(Self {
+            m_type,
+            m_content: m_content.trim_start(),
+        })
+    }
+}
+
+/// Why a dgg frame couldn't be parsed
+#[derive(Debug)]
+pub enum DggParseError {
+    Empty,
+    /// the frame doesn't start with an upper case type like `MSG`
+    InvalidType(String),
+    /// the frame type needs JSON content but the frame only has the type
+    MissingContent(String),
+    Json {
+        m_type: String,
+        err: serde_json::Error,
+    },
+}
+
+impl DggParseError {
+    /// a short name used to count malformed frames
+    pub fn name(&self) -> &'static str {
+        match self {
+            DggParseError::Empty => "empty",
+            DggParseError::InvalidType(_) => "invalid type",
+            DggParseError::MissingContent(_) => "missing content",
+            DggParseError::Json { .. } => "invalid json",
+        }
+    }
+}
+
+impl fmt::Display for DggParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DggParseError::Empty => write!(f, "empty frame"),
+            DggParseError::InvalidType(m_type) => write!(f, "invalid frame type `{}`", m_type),
+            DggParseError::MissingContent(m_type) => write!(f, "{} frame without content", m_type),
+            DggParseError::Json { m_type, err } => write!(f, "invalid {} json, {}", m_type, err),
+        }
+    }
+}
+
+impl std::error::Error for DggParseError {}
+
+/// Counts the frames that couldn't be parsed, by kind of error
+#[derive(Debug, Default)]
+pub struct MalformedFrames {
+    counts: HashMap<&'static str, u64>,
+}
+
+impl MalformedFrames {
+    pub fn record(&mut self, err: &DggParseError) {
+        *self.counts.entry(err.name()).or_default() += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn count(&self, name: &str) -> u64 {
+        self.counts.get(name).copied().unwrap_or_default()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    #[test]
+    fn raw_msg() {
+        let raw_msg = RawDggMsg::try_from(r#"MSG {"nick":"a","data":"b"}"#).unwrap();
+        assert_eq!(raw_msg.m_type, "MSG");
+        assert_eq!(raw_msg.m_content, r#"{"nick":"a","data":"b"}"#);
+
+        // keyword only frames have no content
+        let raw_msg = RawDggMsg::try_from("PING\n").unwrap();
+        assert_eq!(raw_msg.m_type, "PING");
+        assert_eq!(raw_msg.m_content, "");
+    }
+
+    #[test]
+    fn empty_frame() {
+        assert!(matches!(RawDggMsg::try_from(""), Err(DggParseError::Empty)));
+        assert!(matches!(
+            "  \n".parse::<DggEvent>(),
+            Err(DggParseError::Empty)
+        ));
+    }
+
+    #[test]
+    fn invalid_type() {
+        assert!(matches!(
+            "msg {}".parse::<DggEvent>(),
+            Err(DggParseError::InvalidType(m_type)) if m_type == "msg"
+        ));
+        assert!(matches!(
+            r#"{"data":"hi"}"#.parse::<DggEvent>(),
+            Err(DggParseError::InvalidType(_))
+        ));
+    }
+
+    #[test]
+    fn ping() {
+        assert!(matches!("PING".parse(), Ok(DggEvent::Ping(Value::Null))));
+        let Ok(DggEvent::Ping(data)) = r#"PING {"data":1700000000000}"#.parse() else {
+            panic!("expected a ping");
+        };
+        assert_eq!(data["data"], 1700000000000u64);
+    }
+
+    #[test]
+    fn keyword_only_frames() {
+        assert!(matches!(
+            "MSG".parse::<DggEvent>(),
+            Err(DggParseError::MissingContent(m_type)) if m_type == "MSG"
+        ));
+        assert!(matches!(
+            "JOIN ".parse::<DggEvent>(),
+            Err(DggParseError::MissingContent(_))
+        ));
+        // keyword only frames of unknown types are fine
+        assert!(matches!(
+            "REFRESH".parse(),
+            Ok(DggEvent::Other(m_type)) if m_type == "REFRESH"
+        ));
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert!(matches!(
+            r#"MSG {"nick":"a""#.parse::<DggEvent>(),
+            Err(DggParseError::Json { m_type, .. }) if m_type == "MSG"
+        ));
+        // valid json missing fields
+        assert!(matches!(
+            r#"BAN {"nick":"mod"}"#.parse::<DggEvent>(),
+            Err(DggParseError::Json { .. })
+        ));
+        assert!(matches!(
+            "PING not-json".parse::<DggEvent>(),
+            Err(DggParseError::Json { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_type() {
+        let event = r#"GIFTSUB {"nick":"a","data":"b"}"#.parse::<DggEvent>().unwrap();
+        assert!(matches!(&event, DggEvent::Other(m_type) if m_type == "GIFTSUB"));
+        assert!(event.into_chat_event("chat.destiny.gg", "").is_none());
+    }
+
+    #[test]
+    fn msg() {
+        let raw_msg_text = r#"MSG {"id":1,"nick":"Destiny","roles":["USER"],"features":["admin","subscriber"],"createdDate":"2023-11-22T10:00:00Z","timestamp":1700647200000,"data":"hello"}"#;
+        let event = raw_msg_text.parse::<DggEvent>().unwrap();
+        let Some(ChatEvent::Msg(msg)) = event.into_chat_event("chat.destiny.gg", raw_msg_text)
+        else {
+            panic!("expected a chat message");
+        };
+        assert_eq!(msg.author.name, "Destiny");
+        assert_eq!(msg.msg_text, "hello");
+        assert_eq!(msg.author.badges.len(), 2);
+    }
+
+    fn notice_kind(raw_msg_text: &str) -> NoticeKind {
+        let event = raw_msg_text.parse::<DggEvent>().unwrap();
+        match event.into_chat_event("chat.destiny.gg", raw_msg_text) {
+            Some(ChatEvent::Notice(notice)) => notice.kind,
+            other => panic!("expected a notice for `{}`, got {:?}", raw_msg_text, other),
+        }
+    }
+
+    #[test]
+    fn mod_actions() {
+        let muted_at: DateTime<Utc> = "2023-11-22T10:00:00Z".parse().unwrap();
+        assert!(matches!(
+            notice_kind(r#"MUTE {"nick":"Mod","data":"troll","timestamp":1700647200000,"duration":600}"#),
+            NoticeKind::UserMuted { username, muted_by: Some(muted_by), expires_at: Some(expires_at) }
+                if username == "troll" && muted_by == "Mod"
+                    && expires_at == muted_at + chrono::Duration::seconds(600)
+        ));
+        assert!(matches!(
+            notice_kind(r#"MUTE {"nick":"Mod","data":"troll"}"#),
+            NoticeKind::UserMuted {
+                expires_at: None,
+                ..
+            }
+        ));
+
+        assert!(matches!(
+            notice_kind(r#"BAN {"nick":"Mod","data":"troll","timestamp":1700647200000,"duration":3600}"#),
+            NoticeKind::UserBanned { username, banned_by: Some(banned_by), permanent: false, expires_at: Some(_) }
+                if username == "troll" && banned_by == "Mod"
+        ));
+        for frame in [
+            r#"BAN {"nick":"Mod","data":"troll"}"#,
+            r#"BAN {"nick":"Mod","data":"troll","duration":0}"#,
+        ] {
+            assert!(matches!(
+                notice_kind(frame),
+                NoticeKind::UserBanned {
+                    permanent: true,
+                    expires_at: None,
+                    ..
+                }
+            ));
+        }
+
+        assert!(matches!(
+            notice_kind(r#"UNBAN {"nick":"Mod","data":"troll"}"#),
+            NoticeKind::UserUnbanned { username, unbanned_by: Some(unbanned_by) }
+                if username == "troll" && unbanned_by == "Mod"
+        ));
+        assert!(matches!(
+            notice_kind(r#"SUBONLY {"nick":"Mod","data":"on"}"#),
+            NoticeKind::SubOnly { enabled: true, by: Some(by) } if by == "Mod"
+        ));
+        assert!(matches!(
+            notice_kind(r#"SUBONLY {"nick":"Mod","data":"off"}"#),
+            NoticeKind::SubOnly { enabled: false, .. }
+        ));
+    }
+
+    #[test]
+    fn broadcast_and_whisper() {
+        assert!(matches!(
+            notice_kind(r#"BROADCAST {"data":"Destiny is live!","timestamp":1700647200000}"#),
+            NoticeKind::Broadcast { msg_text } if msg_text == "Destiny is live!"
+        ));
+        assert!(matches!(
+            notice_kind(r#"PRIVMSG {"nick":"friend","data":"psst","timestamp":1700647200000}"#),
+            NoticeKind::Whisper { from, msg_text } if from == "friend" && msg_text == "psst"
+        ));
+    }
+
+    #[test]
+    fn polls() {
+        assert!(matches!(
+            notice_kind(r#"POLLSTART {"nick":"Mod","question":"Best chat?","options":["dgg","kick"],"time":30000}"#),
+            NoticeKind::PollStarted { question, options }
+                if question == "Best chat?" && options == ["dgg", "kick"]
+        ));
+        assert!(matches!(
+            notice_kind(r#"POLLSTOP {"question":"Best chat?","options":["dgg","kick"],"totals":[10,3]}"#),
+            NoticeKind::PollEnded { question, results }
+                if question == "Best chat?"
+                    && results == [("dgg".to_string(), 10), ("kick".to_string(), 3)]
+        ));
+    }
+
+    #[test]
+    fn user_list() {
+        let mut users = UserList::default();
+        for frame in [
+            r#"NAMES {"connectioncount":5,"users":[{"nick":"a","features":["subscriber"]},{"nick":"b"}]}"#,
+            r#"JOIN {"nick":"c","timestamp":1700647200000}"#,
+            r#"QUIT {"nick":"a","timestamp":1700647200000}"#,
+        ] {
+            let event = frame.parse::<DggEvent>().unwrap();
+            users.apply(&event);
+            // the user list is only state, it isn't shown
+            assert!(event.into_chat_event("chat.destiny.gg", frame).is_none());
+        }
+        assert_eq!(users.connection_count, 5);
+        let mut nicks: Vec<_> = users.users.keys().map(String::as_str).collect();
+        nicks.sort();
+        assert_eq!(nicks, ["b", "c"]);
+    }
+
+    #[test]
+    fn err() {
+        assert!(matches!(
+            r#"ERR "throttled""#.parse(),
+            Ok(DggEvent::Err(err)) if err == "throttled"
+        ));
+        assert!(matches!(
+            r#"ERR {"description":"muted"}"#.parse(),
+            Ok(DggEvent::Err(err)) if err == "muted"
+        ));
+    }
+
+    #[test]
+    fn malformed_frames() {
+        let mut malformed = MalformedFrames::default();
+        for frame in ["", "msg", "MSG", "MSG {", "MSG }"] {
+            malformed.record(&frame.parse::<DggEvent>().unwrap_err());
+        }
+        assert_eq!(malformed.total(), 5);
+        assert_eq!(malformed.count("empty"), 1);
+        assert_eq!(malformed.count("invalid type"), 1);
+        assert_eq!(malformed.count("missing content"), 1);
+        assert_eq!(malformed.count("invalid json"), 2);
+        // frames of unknown types aren't malformed
+        assert!("GIFTSUB {}".parse::<DggEvent>().is_ok());
+    }
+
+    #[test]
+    fn msg_frame_escapes_text() {
+        assert_eq!(msg_frame("hi"), r#"MSG {"data":"hi"}"#);
+        assert_eq!(msg_frame("say \"hi\"\n"), r#"MSG {"data":"say \"hi\"\n"}"#);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn send_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        let (tx, _rx) = mpsc::channel(16);
+        let (sender, outgoing) = outgoing_channel();
+
+        // queued before the chat is connected
+        sender.send_message("first").await.unwrap();
+        let chat = tokio::spawn(connect(tx, url, Some("secret".to_string()), Some(outgoing)));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket =
+            tokio_tungstenite::accept_hdr_async(stream, |req: &Request, res: Response| {
+                assert_eq!(req.headers()["cookie"], "authtoken=secret");
+                Ok(res)
+            })
+            .await
+            .unwrap();
+
+        let frame = socket.next().await.unwrap().unwrap();
+        assert_eq!(frame.to_text().unwrap(), r#"MSG {"data":"first"}"#);
+        sender.send_message(r#"a "quoted" reply"#).await.unwrap();
+        let frame = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            frame.to_text().unwrap(),
+            r#"MSG {"data":"a \"quoted\" reply"}"#
+        );
+
+        // the queue is gone with the chat
+        chat.abort();
+        assert!(chat.await.unwrap_err().is_cancelled());
+        assert!(sender.send_message("after").await.is_err());
+    }
+}
@@ -0,0 +1,700 @@
+use crate::config::{IrcAuth, IrcNetwork};
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{Author, ChatEvent, ChatLocation, ChatMsg, Fragment};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+pub async fn main(tx: Sender<ChatEvent>, network: IrcNetwork, health: Health) {
+    let address = match ServerAddress::parse(&network.server) {
+        Ok(address) => address,
+        Err(err) => {
+            error!("irc {} can't start, {}", network.server, err);
+            health.finished();
+            return;
+        }
+    };
+    let network_name = network.network.clone().unwrap_or(address.host.clone());
+    let name = format!("irc {}", network_name);
+    supervise(&name, &health, Backoff::default(), || {
+        connect(
+            tx.clone(),
+            address.clone(),
+            network_name.clone(),
+            network.clone(),
+        )
+    })
+    .await
+}
+
+/// The host and port of an `ircs://host:port` or `irc://host:port` url
+#[derive(Debug, Clone)]
+struct ServerAddress {
+    host: String,
+    port: u16,
+    tls: bool,
+}
+
+impl ServerAddress {
+    fn parse(server: &str) -> Result<Self, SourceError> {
+        let url = Url::parse(server)
+            .map_err(|err| SourceError::Other(format!("invalid server url, {}", err)))?;
+        let tls = match url.scheme() {
+            "ircs" => true,
+            "irc" => false,
+            scheme => {
+                return Err(SourceError::Other(format!(
+                    "unknown scheme `{}`, expected ircs or irc",
+                    scheme
+                )))
+            }
+        };
+        let host = url
+            .host_str()
+            .ok_or(SourceError::Other("no host in server url".to_string()))?;
+        Ok(ServerAddress {
+            host: host.to_string(),
+            port: url.port().unwrap_or(if tls { 6697 } else { 6667 }),
+            tls,
+        })
+    }
+}
+
+/// Anything lines can be read from and written to, a plain or a tls tcp stream
+trait IrcStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> IrcStream for T {}
+
+async fn open_stream(address: &ServerAddress) -> Result<Box<dyn IrcStream>, SourceError> {
+    debug!("Connecting to irc {}:{}", address.host, address.port);
+    let stream = TcpStream::connect((address.host.as_str(), address.port))
+        .await
+        .map_err(|err| SourceError::Other(format!("can't connect, {}", err)))?;
+    if !address.tls {
+        return Ok(Box::new(stream));
+    }
+
+    let mut roots = RootCertStore::empty();
+    let certs = rustls_native_certs::load_native_certs()
+        .map_err(|err| SourceError::Other(format!("can't load root certificates, {}", err)))?;
+    roots.add_parsable_certificates(certs);
+    let tls_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(address.host.clone())
+        .map_err(|err| SourceError::Other(format!("invalid server name, {}", err)))?;
+    let stream = TlsConnector::from(Arc::new(tls_config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|err| SourceError::Other(format!("tls handshake failed, {}", err)))?;
+    Ok(Box::new(stream))
+}
+
+async fn connect(
+    tx: Sender<ChatEvent>,
+    address: ServerAddress,
+    network_name: String,
+    network: IrcNetwork,
+) -> Result<(), SourceError> {
+    let stream = open_stream(&address).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut raw_line = vec![];
+
+    let mut nick = network.nick.clone();
+    if let Some(IrcAuth::Sasl { .. }) = network.auth {
+        send_line(&mut writer, "CAP REQ :sasl").await?;
+    }
+    send_line(&mut writer, &format!("NICK {}", nick)).await?;
+    send_line(&mut writer, &format!("USER {} 0 * :{}", nick, nick)).await?;
+
+    loop {
+        raw_line.clear();
+        let read = reader
+            .read_until(b'\n', &mut raw_line)
+            .await
+            .map_err(|err| SourceError::Other(format!("can't read from irc, {}", err)))?;
+        if read == 0 {
+            return Err(SourceError::Closed);
+        }
+        // irc has no set encoding, a line that isn't utf-8 is shown with replacement characters
+        let line = String::from_utf8_lossy(&raw_line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            continue;
+        }
+        debug!("{}", line);
+        let irc_msg = match line.parse::<IrcMessage>() {
+            Ok(irc_msg) => irc_msg,
+            Err(err) => {
+                warn!("Can't parse irc line `{}`, {}", line, err);
+                continue;
+            }
+        };
+
+        match irc_msg.command.as_str() {
+            "PING" => {
+                let pong = format!("PONG :{}", irc_msg.trailing().unwrap_or_default());
+                send_line(&mut writer, &pong).await?;
+            }
+            "CAP" => match irc_msg.params.get(1).map(|param| param.as_str()) {
+                Some("ACK") => send_line(&mut writer, "AUTHENTICATE PLAIN").await?,
+                Some("NAK") => {
+                    return Err(SourceError::Other(format!(
+                        "{} doesn't support sasl",
+                        address.host
+                    )))
+                }
+                _ => {}
+            },
+            "AUTHENTICATE" if irc_msg.trailing() == Some("+") => {
+                if let Some(IrcAuth::Sasl { username, password }) = &network.auth {
+                    let username = username.as_deref().unwrap_or(&network.nick);
+                    let plain = format!("{}\0{}\0{}", username, username, password);
+                    let encoded = base64::engine::general_purpose::STANDARD.encode(plain);
+                    send_line(&mut writer, &format!("AUTHENTICATE {}", encoded)).await?;
+                }
+            }
+            // RPL_SASLSUCCESS
+            "903" => send_line(&mut writer, "CAP END").await?,
+            // ERR_SASLFAIL, ERR_SASLTOOLONG
+            "904" | "905" => {
+                return Err(SourceError::Other(format!(
+                    "sasl login to {} failed",
+                    address.host
+                )))
+            }
+            // ERR_NICKNAMEINUSE, only sent before the welcome since the nick never changes afterwards
+            "433" => {
+                nick.push('_');
+                send_line(&mut writer, &format!("NICK {}", nick)).await?;
+            }
+            // RPL_WELCOME
+            "001" => {
+                if let Some(IrcAuth::NickServ { password }) = &network.auth {
+                    let identify =
+                        format!("PRIVMSG NickServ :IDENTIFY {} {}", network.nick, password);
+                    send_line(&mut writer, &identify).await?;
+                }
+                let channels: Vec<String> =
+                    network.channels.iter().map(|c| channel_name(c)).collect();
+                send_line(&mut writer, &format!("JOIN {}", channels.join(","))).await?;
+                info!(
+                    "joined irc {} {} as {}",
+                    network_name,
+                    channels.join(" "),
+                    nick
+                );
+            }
+            "ERROR" => {
+                return Err(SourceError::Other(format!(
+                    "irc server closed the connection, {}",
+                    irc_msg.trailing().unwrap_or_default()
+                )))
+            }
+            "PRIVMSG" => {
+                if let Some(chat_msg) = irc_msg.chat_msg(&network_name, line) {
+                    tx.send(chat_msg.into()).await?;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn send_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> Result<(), SourceError> {
+    writer
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .map_err(|err| SourceError::Other(format!("can't write to irc, {}", err)))
+}
+
+/// channel_name() adds the `#` to a channel given without one
+fn channel_name(channel: &str) -> String {
+    if channel.starts_with(['#', '&']) {
+        channel.to_string()
+    } else {
+        format!("#{}", channel)
+    }
+}
+
+/// A line of IRC with IRCv3 tags, like
+/// `@badges=moderator/1;display-name=Bob :bob!bob@bob.tmi.twitch.tv PRIVMSG #channel :hi`
+#[derive(Debug, Default, PartialEq)]
+pub struct IrcMessage {
+    pub tags: HashMap<String, String>,
+    /// the server or `nick!user@host` that sent the line
+    pub prefix: Option<String>,
+    pub command: String,
+    /// the middle params and the trailing param, which is the only one that can have spaces
+    pub params: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum IrcParseError {
+    Empty,
+    MissingCommand,
+}
+
+impl fmt::Display for IrcParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrcParseError::Empty => write!(f, "empty line"),
+            IrcParseError::MissingCommand => write!(f, "no command"),
+        }
+    }
+}
+
+impl std::error::Error for IrcParseError {}
+
+impl FromStr for IrcMessage {
+    type Err = IrcParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        if rest.is_empty() {
+            return Err(IrcParseError::Empty);
+        }
+        let mut irc_msg = IrcMessage::default();
+
+        if let Some(tagged) = rest.strip_prefix('@') {
+            let (tags, after) = tagged.split_once(' ').unwrap_or((tagged, ""));
+            for tag in tags.split(';').filter(|tag| !tag.is_empty()) {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                irc_msg
+                    .tags
+                    .insert(key.to_string(), unescape_tag_value(value));
+            }
+            rest = after.trim_start();
+        }
+
+        if let Some(prefixed) = rest.strip_prefix(':') {
+            let (prefix, after) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+            irc_msg.prefix = Some(prefix.to_string());
+            rest = after.trim_start();
+        }
+
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut middle = middle.split(' ').filter(|param| !param.is_empty());
+        irc_msg.command = middle
+            .next()
+            .ok_or(IrcParseError::MissingCommand)?
+            .to_uppercase();
+        irc_msg.params = middle.map(|param| param.to_string()).collect();
+        if let Some(trailing) = trailing {
+            irc_msg.params.push(trailing.to_string());
+        }
+        Ok(irc_msg)
+    }
+}
+
+/// unescape_tag_value() undoes the IRCv3 escaping of `;`, spaces, `\`, CR and LF in tag values
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+impl IrcMessage {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .get(key)
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// the last param, the message text of a PRIVMSG
+    pub fn trailing(&self) -> Option<&str> {
+        self.params.last().map(|param| param.as_str())
+    }
+
+    /// the nick part of a `nick!user@host` prefix
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        prefix.split_once('!').map(|(nick, _)| nick)
+    }
+
+    /// the text of a PRIVMSG, `/me` messages are wrapped in a CTCP ACTION which is stripped,
+    /// `None` for every other CTCP like VERSION
+    pub fn text(&self) -> Option<&str> {
+        let text = self.trailing()?;
+        match text.strip_prefix('\u{1}') {
+            Some(ctcp) => ctcp
+                .strip_prefix("ACTION ")
+                .map(|text| text.trim_end_matches('\u{1}')),
+            None => Some(text),
+        }
+    }
+
+    /// chat_msg() turns a PRIVMSG sent to a channel into a message of `{network}/{channel}`,
+    /// messages sent straight to us are dropped
+    fn chat_msg(&self, network: &str, raw_msg_text: &str) -> Option<ChatMsg> {
+        let target = self.params.first()?;
+        if !target.starts_with(['#', '&']) {
+            return None;
+        }
+        let text = self.text()?;
+        let nick = self.nick().or(self.prefix.as_deref())?;
+
+        Some(ChatMsg {
+            id: self.tag("msgid").map(|id| id.to_string()),
+            location: ChatLocation::Irc,
+            channel: format!("{}/{}", network, target),
+            // the `time` tag of the IRCv3 server-time extension, when the server sends it
+            timestamp: self
+                .tag("time")
+                .and_then(|time| time.parse::<DateTime<Utc>>().ok())
+                .unwrap_or_else(Utc::now),
+            stream_offset: None,
+            msg_text: text.to_string(),
+            fragments: Fragment::parse_text(text),
+            author: Author {
+                name: nick.to_string(),
+                ..Author::default()
+            },
+            raw_full_msg: raw_msg_text.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::Lines;
+    use tokio::net::tcp::OwnedReadHalf;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn parse_tags_prefix_and_trailing() {
+        let irc_msg: IrcMessage =
+            r"@time=2023-11-22T10:00:00.000Z;msgid=abc;+draft/reply=x;flag :bob!bob@host PRIVMSG #rust :hello there :)"
+                .parse()
+                .unwrap();
+        assert_eq!(irc_msg.tag("time"), Some("2023-11-22T10:00:00.000Z"));
+        assert_eq!(irc_msg.tag("msgid"), Some("abc"));
+        assert_eq!(irc_msg.tag("+draft/reply"), Some("x"));
+        // tags without a value are kept, but read as missing
+        assert_eq!(irc_msg.tags["flag"], "");
+        assert_eq!(irc_msg.tag("flag"), None);
+        assert_eq!(irc_msg.prefix.as_deref(), Some("bob!bob@host"));
+        assert_eq!(irc_msg.nick(), Some("bob"));
+        assert_eq!(irc_msg.command, "PRIVMSG");
+        assert_eq!(irc_msg.params, ["#rust", "hello there :)"]);
+        assert_eq!(irc_msg.trailing(), Some("hello there :)"));
+    }
+
+    #[test]
+    fn parse_without_tags_or_prefix() {
+        let irc_msg: IrcMessage = "PING :irc.example.com\r\n".parse().unwrap();
+        assert_eq!(
+            irc_msg,
+            IrcMessage {
+                command: "PING".to_string(),
+                params: vec!["irc.example.com".to_string()],
+                ..IrcMessage::default()
+            }
+        );
+
+        let irc_msg: IrcMessage = ":irc.example.com 001 tester Welcome".parse().unwrap();
+        assert_eq!(irc_msg.prefix.as_deref(), Some("irc.example.com"));
+        // a server prefix has no nick
+        assert_eq!(irc_msg.nick(), None);
+        assert_eq!(irc_msg.command, "001");
+        assert_eq!(irc_msg.params, ["tester", "Welcome"]);
+    }
+
+    #[test]
+    fn parse_command_is_upper_case() {
+        let irc_msg: IrcMessage = "ping  x  y".parse().unwrap();
+        assert_eq!(irc_msg.command, "PING");
+        assert_eq!(irc_msg.params, ["x", "y"]);
+        assert_eq!(irc_msg.trailing(), Some("y"));
+    }
+
+    #[test]
+    fn parse_empty_trailing() {
+        let irc_msg: IrcMessage = ":bob!bob@host PRIVMSG #rust :".parse().unwrap();
+        assert_eq!(irc_msg.params, ["#rust", ""]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            "".parse::<IrcMessage>(),
+            Err(IrcParseError::Empty)
+        ));
+        assert!(matches!(
+            "\r\n".parse::<IrcMessage>(),
+            Err(IrcParseError::Empty)
+        ));
+        assert!(matches!(
+            "@a=b :prefix".parse::<IrcMessage>(),
+            Err(IrcParseError::MissingCommand)
+        ));
+    }
+
+    #[test]
+    fn unescape_tags() {
+        assert_eq!(unescape_tag_value(r"a\sb\:c"), "a b;c");
+        assert_eq!(unescape_tag_value(r"\\ \r\n"), "\\ \r\n");
+        // unknown escapes drop the backslash and a trailing backslash is dropped
+        assert_eq!(unescape_tag_value(r"\x\"), "x");
+        assert_eq!(unescape_tag_value("plain"), "plain");
+
+        let irc_msg: IrcMessage = r"@system-msg=Bob\ssubscribed\:\s3\smonths PING"
+            .parse()
+            .unwrap();
+        assert_eq!(irc_msg.tag("system-msg"), Some("Bob subscribed; 3 months"));
+    }
+
+    #[test]
+    fn ctcp_text() {
+        let action: IrcMessage = ":bob!b@h PRIVMSG #rust :\u{1}ACTION waves\u{1}"
+            .parse()
+            .unwrap();
+        assert_eq!(action.text(), Some("waves"));
+        let version: IrcMessage = ":bob!b@h PRIVMSG #rust :\u{1}VERSION\u{1}".parse().unwrap();
+        assert_eq!(version.text(), None);
+    }
+
+    #[test]
+    fn chat_msgs() {
+        let line =
+            "@time=2023-11-22T10:00:00.000Z;msgid=abc :bob!bob@host PRIVMSG #rust :hi @alice";
+        let chat_msg = line
+            .parse::<IrcMessage>()
+            .unwrap()
+            .chat_msg("libera", line)
+            .unwrap();
+        assert_eq!(chat_msg.channel, "libera/#rust");
+        assert_eq!(chat_msg.author.name, "bob");
+        assert_eq!(chat_msg.id.as_deref(), Some("abc"));
+        assert_eq!(chat_msg.timestamp.timestamp(), 1700647200);
+        assert_eq!(
+            chat_msg.fragments,
+            vec![
+                Fragment::Text("hi ".to_string()),
+                Fragment::Mention("alice".to_string())
+            ]
+        );
+
+        // messages sent straight to us are dropped
+        let private: IrcMessage = ":bob!bob@host PRIVMSG tester :psst".parse().unwrap();
+        assert!(private.chat_msg("libera", "").is_none());
+    }
+
+    #[test]
+    fn server_addresses() {
+        let plain = ServerAddress::parse("irc://localhost").unwrap();
+        assert_eq!(
+            (plain.host.as_str(), plain.port, plain.tls),
+            ("localhost", 6667, false)
+        );
+        let tls = ServerAddress::parse("ircs://irc.libera.chat:7000").unwrap();
+        assert_eq!(
+            (tls.host.as_str(), tls.port, tls.tls),
+            ("irc.libera.chat", 7000, true)
+        );
+        assert!(ServerAddress::parse("https://irc.libera.chat").is_err());
+        assert!(ServerAddress::parse("irc.libera.chat").is_err());
+    }
+
+    #[test]
+    fn channel_names() {
+        assert_eq!(channel_name("rust"), "#rust");
+        assert_eq!(channel_name("#rust"), "#rust");
+        assert_eq!(channel_name("&local"), "&local");
+    }
+
+    /// A local irc server reading the lines of a single client
+    struct StandIn {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: tokio::net::tcp::OwnedWriteHalf,
+    }
+
+    impl StandIn {
+        async fn next_line(&mut self) -> String {
+            self.lines.next_line().await.unwrap().unwrap()
+        }
+
+        async fn send(&mut self, line: &str) {
+            self.writer
+                .write_all(format!("{}\r\n", line).as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    /// start() connects `network` to a local stand-in over plain `irc://`
+    async fn start(
+        mut network: IrcNetwork,
+    ) -> (
+        StandIn,
+        mpsc::Receiver<ChatEvent>,
+        tokio::task::JoinHandle<Result<(), SourceError>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        network.server = format!("irc://localhost:{}", listener.local_addr().unwrap().port());
+        let address = ServerAddress::parse(&network.server).unwrap();
+        let (tx, rx) = mpsc::channel(16);
+        let chat = tokio::spawn(connect(tx, address, "local".to_string(), network));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, writer) = stream.into_split();
+        let stand_in = StandIn {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        (stand_in, rx, chat)
+    }
+
+    fn network(auth: Option<IrcAuth>) -> IrcNetwork {
+        IrcNetwork {
+            server: String::new(),
+            network: None,
+            nick: "tester".to_string(),
+            channels: vec!["rust".to_string(), "#libera".to_string()],
+            auth,
+        }
+    }
+
+    #[tokio::test]
+    async fn join_and_read() {
+        let (mut server, mut rx, chat) = start(network(None)).await;
+        assert_eq!(server.next_line().await, "NICK tester");
+        assert_eq!(server.next_line().await, "USER tester 0 * :tester");
+
+        server
+            .send(":irc.local 433 * tester :Nickname is already in use")
+            .await;
+        assert_eq!(server.next_line().await, "NICK tester_");
+        server.send(":irc.local 001 tester_ :Welcome").await;
+        assert_eq!(server.next_line().await, "JOIN #rust,#libera");
+
+        server.send("PING :irc.local").await;
+        assert_eq!(server.next_line().await, "PONG :irc.local");
+
+        server.send(":bob!bob@host PRIVMSG tester_ :psst").await;
+        server.send(":bob!bob@host PRIVMSG #rust :hello").await;
+        let Some(ChatEvent::Msg(msg)) = rx.recv().await else {
+            panic!("expected a message");
+        };
+        assert_eq!(msg.channel, "local/#rust");
+        assert_eq!(msg.msg_text, "hello");
+        assert_eq!(msg.raw_full_msg, ":bob!bob@host PRIVMSG #rust :hello");
+
+        // latin-1 from an old client doesn't drop the connection
+        server
+            .writer
+            .write_all(b":bob!bob@host PRIVMSG #rust :caf\xe9\r\n")
+            .await
+            .unwrap();
+        let Some(ChatEvent::Msg(msg)) = rx.recv().await else {
+            panic!("expected a message");
+        };
+        assert_eq!(msg.msg_text, "caf\u{fffd}");
+
+        server.send("ERROR :Closing link").await;
+        assert!(matches!(
+            chat.await.unwrap(),
+            Err(SourceError::Other(err)) if err.ends_with("Closing link")
+        ));
+    }
+
+    #[tokio::test]
+    async fn nickserv() {
+        let auth = IrcAuth::NickServ {
+            password: "hunter2".to_string(),
+        };
+        let (mut server, _rx, chat) = start(network(Some(auth))).await;
+        server.next_line().await;
+        server.next_line().await;
+        server.send(":irc.local 001 tester :Welcome").await;
+        assert_eq!(
+            server.next_line().await,
+            "PRIVMSG NickServ :IDENTIFY tester hunter2"
+        );
+        assert_eq!(server.next_line().await, "JOIN #rust,#libera");
+
+        drop(server);
+        assert!(matches!(chat.await.unwrap(), Err(SourceError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn sasl() {
+        let auth = IrcAuth::Sasl {
+            username: Some("account".to_string()),
+            password: "hunter2".to_string(),
+        };
+        let (mut server, _rx, chat) = start(network(Some(auth))).await;
+        assert_eq!(server.next_line().await, "CAP REQ :sasl");
+        assert_eq!(server.next_line().await, "NICK tester");
+        assert_eq!(server.next_line().await, "USER tester 0 * :tester");
+
+        server.send(":irc.local CAP * ACK :sasl").await;
+        assert_eq!(server.next_line().await, "AUTHENTICATE PLAIN");
+        server.send("AUTHENTICATE +").await;
+        let encoded = base64::engine::general_purpose::STANDARD.encode("account\0account\0hunter2");
+        assert_eq!(
+            server.next_line().await,
+            format!("AUTHENTICATE {}", encoded)
+        );
+        server
+            .send(":irc.local 903 tester :SASL authentication successful")
+            .await;
+        assert_eq!(server.next_line().await, "CAP END");
+
+        server
+            .send(":irc.local 904 tester :SASL authentication failed")
+            .await;
+        assert!(matches!(
+            chat.await.unwrap(),
+            Err(SourceError::Other(err)) if err == "sasl login to localhost failed"
+        ));
+    }
+
+    #[tokio::test]
+    async fn sasl_not_supported() {
+        let auth = IrcAuth::Sasl {
+            username: None,
+            password: "hunter2".to_string(),
+        };
+        let (mut server, _rx, chat) = start(network(Some(auth))).await;
+        server.next_line().await;
+        server.send(":irc.local CAP * NAK :sasl").await;
+        assert!(matches!(
+            chat.await.unwrap(),
+            Err(SourceError::Other(err)) if err == "localhost doesn't support sasl"
+        ));
+    }
+}
@@ -1,61 +1,578 @@
-use crate::{types::ChatMsg, utils::json_string};
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{
+    Author, Badge, ChatEvent, ChatLocation, ChatMsg, ChatNotice, Fragment, NoticeKind, Role,
+};
+use crate::utils::json_string;
 use chrono::{DateTime, Utc};
-use futures_util::{StreamExt, SinkExt};
-use log::debug;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
-use tokio::sync::broadcast::Sender;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use url::Url;
 
+/// emote images are served from `{EMOTE_CDN_URL}/{emote id}/fullsize`
+const EMOTE_CDN_URL: &str = "https://files.kick.com/emotes";
 
-pub async fn main(tx: Sender<ChatMsg>) {
-    debug!("Connecting to kick socket");
-    let ws_url = Url::parse("wss://ws-us2.pusher.com/app/eb1d5f283081a78b932c?protocol=7&client=js&version=7.6.0&flash=false").unwrap();
-
-    let (mut socket, _) = connect_async(ws_url).await.expect("Can't connect");
-
-    // xqc = 668
-    // westcol = 669512
-    // destiny = 1764849
-    // garydavid = 72124
-    // roshtein = 4598
-    let hello_1 = Message::Text(
-        r#"{"event":"pusher:subscribe","data":{"auth":"","channel":"chatrooms.4598.v2"}}"#
-            .to_string(),
-    );
-    let msg = socket.next().await.expect("Error reading message").unwrap();
-    debug!("{}", msg.to_text().unwrap());
-    socket.send(hello_1).await.unwrap();
-    let msg = socket.next().await.expect("Error reading message").unwrap();
-    debug!("{}", msg.to_text().unwrap());
-    // socket.send(hello_2).unwrap();
-    let msg = socket.next().await.expect("Error reading message").unwrap();
-    debug!("{}", msg.to_text().unwrap());
+/// where the slug to chatroom id mapping is cached
+const CHATROOM_CACHE_PATH: &str = "tmp/kick_chatrooms.json";
+
+pub async fn main(
+    tx: Sender<ChatEvent>,
+    channel: String,
+    api_url: String,
+    pusher_url: String,
+    health: Health,
+) {
+    let name = format!("kick {}", channel);
+    supervise(&name, &health, Backoff::default(), || {
+        connect(
+            tx.clone(),
+            channel.clone(),
+            api_url.clone(),
+            pusher_url.clone(),
+        )
+    })
+    .await
+}
+
+async fn connect(
+    tx: Sender<ChatEvent>,
+    channel: String,
+    api_url: String,
+    pusher_url: String,
+) -> Result<(), SourceError> {
+    let chatroom_id = get_chatroom_id(&api_url, &channel).await?;
+    connect_pusher(tx, channel, pusher_url, chatroom_id).await
+}
+
+/// connect_pusher() subscribes to the chatroom on the pusher websocket and reads its events
+async fn connect_pusher(
+    tx: Sender<ChatEvent>,
+    channel: String,
+    pusher_url: String,
+    chatroom_id: u64,
+) -> Result<(), SourceError> {
+    debug!("Connecting to kick socket {}", pusher_url);
+    let ws_url = Url::parse(&pusher_url).map_err(|err| SourceError::Other(err.to_string()))?;
+
+    let (mut socket, _) = connect_async(ws_url).await?;
+    let mut pusher = PusherClient::new(format!("chatrooms.{}.v2", chatroom_id));
 
     loop {
-        let msg: Message = socket.next().await.expect("Error reading message").unwrap();
+        let msg = match timeout(pusher.activity_timeout, socket.next()).await {
+            Ok(msg) => msg.ok_or(SourceError::Closed)??,
+            Err(_) => {
+                if pusher.awaiting_pong {
+                    return Err(SourceError::Other("pusher ping timed out".to_string()));
+                }
+                debug!("No pusher activity, sending ping");
+                pusher.awaiting_pong = true;
+                socket.send(pusher_frame("pusher:ping")).await?;
+                continue;
+            }
+        };
         if !msg.is_text() {
             continue;
         }
 
-        let raw_msg_text = msg.to_text().unwrap();
+        let raw_msg_text = msg.to_text()?;
         if raw_msg_text.is_empty() {
             continue;
         }
+        debug!("{}", raw_msg_text);
 
-        let event: Event = serde_json::from_str(raw_msg_text).unwrap();
+        let event = match PusherEvent::parse(raw_msg_text) {
+            Ok(event) => event,
+            Err(err) => {
+                warn!("Can't parse pusher frame `{}`, {}", raw_msg_text, err);
+                continue;
+            }
+        };
 
-        let chat_msg = ChatMsg {
-            author: event.data.sender.username,
-            location: crate::types::ChatLocation::Kick,
-            msg_text: event.data.content,
-            timestamp: event.data.created_at,
-            raw_full_msg: raw_msg_text.to_string(),
+        match pusher.on_event(event)? {
+            PusherAction::Send(reply) => socket.send(reply).await?,
+            PusherAction::Kick(event) => {
+                let chat_event = event.into_chat_event(&channel, raw_msg_text);
+                tx.send(chat_event).await?;
+            }
+            PusherAction::None => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PusherState {
+    /// waiting for `pusher:connection_established`
+    Connecting,
+    /// sent `pusher:subscribe`, waiting for `pusher_internal:subscription_succeeded`
+    Subscribing,
+    Subscribed,
+}
+
+/// What the connection loop should do after a pusher event
+#[derive(Debug)]
+enum PusherAction {
+    Send(Message),
+    Kick(KickEvent),
+    None,
+}
+
+/// The client side of the pusher protocol for a single channel subscription
+#[derive(Debug)]
+struct PusherClient {
+    channel: String,
+    state: PusherState,
+    /// how long the connection can be silent before we ping the server
+    activity_timeout: Duration,
+    /// we sent a `pusher:ping` and are waiting for any frame from the server
+    awaiting_pong: bool,
+}
+
+impl PusherClient {
+    fn new(channel: String) -> Self {
+        Self {
+            channel,
+            state: PusherState::Connecting,
+            activity_timeout: Duration::from_secs(120),
+            awaiting_pong: false,
+        }
+    }
+
+    /// on_event() advances the state machine, every frame from the server counts as activity
+    fn on_event(&mut self, event: PusherEvent) -> Result<PusherAction, SourceError> {
+        self.awaiting_pong = false;
+        match event {
+            PusherEvent::ConnectionEstablished {
+                socket_id,
+                activity_timeout,
+            } => {
+                info!("Pusher connection established, socket id {}", socket_id);
+                if let Some(activity_timeout) = activity_timeout {
+                    self.activity_timeout = Duration::from_secs(activity_timeout);
+                }
+                self.state = PusherState::Subscribing;
+                Ok(PusherAction::Send(Message::Text(format!(
+                    r#"{{"event":"pusher:subscribe","data":{{"auth":"","channel":"{}"}}}}"#,
+                    self.channel
+                ))))
+            }
+            PusherEvent::SubscriptionSucceeded { channel } => {
+                info!("Subscribed to pusher channel {}", channel);
+                if channel == self.channel {
+                    self.state = PusherState::Subscribed;
+                }
+                Ok(PusherAction::None)
+            }
+            PusherEvent::SubscriptionError { channel, error } => Err(SourceError::Other(format!(
+                "can't subscribe to pusher channel {}, {}",
+                channel, error
+            ))),
+            PusherEvent::Ping => Ok(PusherAction::Send(pusher_frame("pusher:pong"))),
+            PusherEvent::Pong => Ok(PusherAction::None),
+            PusherEvent::Error { code, message } => match code {
+                // 4000-4299 mean the server is about to close the connection
+                Some(code @ 4000..=4299) => Err(SourceError::Other(format!(
+                    "pusher error {}, {}",
+                    code, message
+                ))),
+                _ => {
+                    warn!("Pusher error {:?}, {}", code, message);
+                    Ok(PusherAction::None)
+                }
+            },
+            PusherEvent::Kick(event) => {
+                if self.state != PusherState::Subscribed {
+                    debug!("Got a kick event in state {:?}", self.state);
+                }
+                Ok(PusherAction::Kick(event))
+            }
+            PusherEvent::Other { event, channel } => {
+                debug!("Ignoring pusher event {} on {:?}", event, channel);
+                Ok(PusherAction::None)
+            }
+        }
+    }
+}
+
+fn pusher_frame(event: &str) -> Message {
+    Message::Text(format!(r#"{{"event":"{}","data":{{}}}}"#, event))
+}
+
+/// A frame of the pusher protocol, `data` is usually a JSON encoded string
+/// but some events (like `pusher:error`) send it as an object
+#[derive(Debug, Deserialize)]
+struct PusherFrame {
+    event: String,
+    #[serde(default)]
+    data: Value,
+    channel: Option<String>,
+}
+
+impl PusherFrame {
+    fn data<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        match &self.data {
+            Value::String(raw_data) => serde_json::from_str(raw_data),
+            data => T::deserialize(data),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PusherEvent {
+    ConnectionEstablished {
+        socket_id: String,
+        activity_timeout: Option<u64>,
+    },
+    SubscriptionSucceeded {
+        channel: String,
+    },
+    SubscriptionError {
+        channel: String,
+        error: String,
+    },
+    Ping,
+    Pong,
+    Error {
+        code: Option<u16>,
+        message: String,
+    },
+    Kick(KickEvent),
+    Other {
+        event: String,
+        channel: Option<String>,
+    },
+}
+
+impl PusherEvent {
+    fn parse(raw_frame: &str) -> serde_json::Result<Self> {
+        let frame: PusherFrame = serde_json::from_str(raw_frame)?;
+        let event = match frame.event.as_str() {
+            "pusher:connection_established" => {
+                let data: ConnectionEstablished = frame.data()?;
+                PusherEvent::ConnectionEstablished {
+                    socket_id: data.socket_id,
+                    activity_timeout: data.activity_timeout,
+                }
+            }
+            "pusher_internal:subscription_succeeded" => PusherEvent::SubscriptionSucceeded {
+                channel: frame.channel.unwrap_or_default(),
+            },
+            "pusher:subscription_error" => PusherEvent::SubscriptionError {
+                channel: frame.channel.clone().unwrap_or_default(),
+                error: frame.data.to_string(),
+            },
+            "pusher:ping" => PusherEvent::Ping,
+            "pusher:pong" => PusherEvent::Pong,
+            "pusher:error" => {
+                let data: PusherError = frame.data()?;
+                PusherEvent::Error {
+                    code: data.code,
+                    message: data.message,
+                }
+            }
+            r"App\Events\ChatMessageEvent" => {
+                let event: Event = serde_json::from_str(raw_frame)?;
+                PusherEvent::Kick(KickEvent::ChatMessage(event.data))
+            }
+            r"App\Events\MessageDeletedEvent" => {
+                PusherEvent::Kick(KickEvent::MessageDeleted(frame.data()?))
+            }
+            r"App\Events\UserBannedEvent" => {
+                PusherEvent::Kick(KickEvent::UserBanned(frame.data()?))
+            }
+            r"App\Events\PinnedMessageCreatedEvent" => {
+                PusherEvent::Kick(KickEvent::PinnedMessageCreated(frame.data()?))
+            }
+            r"App\Events\SubscriptionEvent" => {
+                PusherEvent::Kick(KickEvent::Subscription(frame.data()?))
+            }
+            r"App\Events\GiftedSubscriptionsEvent" => {
+                PusherEvent::Kick(KickEvent::GiftedSubscriptions(frame.data()?))
+            }
+            r"App\Events\StreamHostEvent" => {
+                PusherEvent::Kick(KickEvent::StreamHost(frame.data()?))
+            }
+            _ => PusherEvent::Other {
+                event: frame.event,
+                channel: frame.channel,
+            },
         };
-        tx.send(chat_msg).unwrap();
+        Ok(event)
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ConnectionEstablished {
+    socket_id: String,
+    activity_timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PusherError {
+    code: Option<u16>,
+    #[serde(default)]
+    message: String,
+}
+
+/// The cached chatroom ids by kick api url and then channel slug,
+/// so the ids of another api (like a local mock) don't mix with those of kick.com
+type ChatroomCache = HashMap<String, HashMap<String, u64>>;
+
+/// held while the cache file is read or written, sources start together and would overwrite each other
+static CHATROOM_CACHE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// get_chatroom_id() returns the pusher chatroom id of a channel slug,
+/// the id is looked up in the on disk cache first and only then asked from the kick api
+pub async fn get_chatroom_id(api_url: &str, channel: &str) -> Result<u64, SourceError> {
+    get_cached_chatroom_id(Path::new(CHATROOM_CACHE_PATH), api_url, channel).await
+}
+
+async fn get_cached_chatroom_id(
+    cache_path: &Path,
+    api_url: &str,
+    channel: &str,
+) -> Result<u64, SourceError> {
+    let api_url = api_url.trim_end_matches('/');
+    {
+        let _lock = CHATROOM_CACHE_LOCK.lock().await;
+        let cache = read_chatroom_cache(cache_path).await;
+        if let Some(chatroom_id) = cache.get(api_url).and_then(|ids| ids.get(channel)) {
+            return Ok(*chatroom_id);
+        }
+    }
+
+    let url = format!("{}/api/v2/channels/{}", api_url, channel);
+    info!("Looking up kick chatroom id of {}", channel);
+    let res_text = reqwest::Client::new()
+        .get(url)
+        .header("Accept", "application/json")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let channel_info: ChannelInfo = serde_json::from_str(&res_text)
+        .map_err(|err| SourceError::Other(format!("unexpected kick channel info, {}", err)))?;
+    let chatroom_id = channel_info.chatroom.id;
+
+    // read again, another source could have written the cache during the lookup
+    let _lock = CHATROOM_CACHE_LOCK.lock().await;
+    let mut cache = read_chatroom_cache(cache_path).await;
+    cache
+        .entry(api_url.to_string())
+        .or_default()
+        .insert(channel.to_string(), chatroom_id);
+    if let Err(err) = write_chatroom_cache(cache_path, &cache).await {
+        warn!("Can't write kick chatroom cache, {}", err);
+    }
+    Ok(chatroom_id)
+}
+
+async fn read_chatroom_cache(path: &Path) -> ChatroomCache {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()
+        .and_then(|raw_cache| serde_json::from_str(&raw_cache).ok())
+        .unwrap_or_default()
+}
+
+/// write_chatroom_cache() writes a temp file next to the cache and renames it,
+/// so a crash halfway never leaves a truncated cache behind
+async fn write_chatroom_cache(path: &Path, cache: &ChatroomCache) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_string_pretty(cache)?).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelInfo {
+    chatroom: Chatroom,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chatroom {
+    id: u64,
+}
+
+/// The app events kick sends on a chatroom channel
+#[derive(Debug)]
+enum KickEvent {
+    ChatMessage(Data),
+    MessageDeleted(MessageDeleted),
+    UserBanned(UserBanned),
+    PinnedMessageCreated(PinnedMessageCreated),
+    Subscription(Subscription),
+    GiftedSubscriptions(GiftedSubscriptions),
+    StreamHost(StreamHost),
+}
+
+impl KickEvent {
+    fn into_chat_event(self, channel: &str, raw_msg_text: &str) -> ChatEvent {
+        let kind = match self {
+            KickEvent::ChatMessage(data) => {
+                let fragments = parse_fragments(&data.content);
+                return ChatMsg {
+                    id: Some(data.id),
+                    author: data.sender.author(),
+                    location: ChatLocation::Kick,
+                    channel: channel.to_string(),
+                    msg_text: fragments.iter().map(Fragment::plain_text).collect(),
+                    fragments,
+                    timestamp: data.created_at,
+                    stream_offset: None,
+                    raw_full_msg: raw_msg_text.to_string(),
+                }
+                .into();
+            }
+            KickEvent::MessageDeleted(deleted) => NoticeKind::MsgDeleted {
+                msg_id: deleted.message.id,
+            },
+            KickEvent::UserBanned(banned) => NoticeKind::UserBanned {
+                username: banned.user.username,
+                banned_by: banned.banned_by.map(|banned_by| banned_by.username),
+                permanent: banned.permanent,
+                expires_at: if banned.permanent {
+                    None
+                } else {
+                    banned.expires_at
+                },
+            },
+            KickEvent::PinnedMessageCreated(pinned) => NoticeKind::MsgPinned {
+                author: pinned.message.sender.username,
+                msg_text: pinned.message.content,
+            },
+            KickEvent::Subscription(sub) => NoticeKind::Subscription {
+                username: sub.username,
+                months: sub.months,
+                tier: None,
+                msg_text: None,
+            },
+            KickEvent::GiftedSubscriptions(gifted) => NoticeKind::GiftedSubs {
+                gifter: gifted.gifter_username,
+                count: gifted.gifted_usernames.len() as u32,
+                recipients: gifted.gifted_usernames,
+            },
+            KickEvent::StreamHost(host) => NoticeKind::Host {
+                host: host.host_username,
+                viewers: host.number_viewers,
+                msg_text: host.optional_message.filter(|msg| !msg.is_empty()),
+            },
+        };
+        ChatNotice {
+            location: ChatLocation::Kick,
+            channel: channel.to_string(),
+            timestamp: Utc::now(),
+            stream_offset: None,
+            kind,
+            raw_full_msg: raw_msg_text.to_string(),
+        }
+        .into()
+    }
+}
+
+/// parse_fragments() splits the content of a kick message into text and emotes,
+/// kick embeds emotes as `[emote:12345:KEKW]`
+fn parse_fragments(content: &str) -> Vec<Fragment> {
+    let mut fragments = vec![];
+    let mut text = String::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("[emote:") {
+        let emote = rest[start..].find(']').and_then(|end| {
+            parse_emote(&rest[start + "[emote:".len()..start + end]).map(|e| (e, end))
+        });
+        match emote {
+            Some((emote, end)) => {
+                text.push_str(&rest[..start]);
+                if !text.is_empty() {
+                    fragments.extend(Fragment::parse_text(&std::mem::take(&mut text)));
+                }
+                fragments.push(emote);
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                text.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        fragments.extend(Fragment::parse_text(&text));
+    }
+    fragments
+}
+
+/// parse_emote() parses the `12345:KEKW` inside of an emote token
+fn parse_emote(token: &str) -> Option<Fragment> {
+    let (id, name) = token.split_once(':')?;
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) || name.is_empty() {
+        return None;
+    }
+    Some(Fragment::Emote {
+        id: id.to_string(),
+        name: name.to_string(),
+        url: format!("{}/{}/fullsize", EMOTE_CDN_URL, id),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDeleted {
+    message: MessageRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageRef {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserBanned {
+    user: MsgSender,
+    banned_by: Option<MsgSender>,
+    #[serde(default)]
+    permanent: bool,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PinnedMessageCreated {
+    message: Data,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subscription {
+    username: String,
+    months: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiftedSubscriptions {
+    gifter_username: String,
+    #[serde(default)]
+    gifted_usernames: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamHost {
+    host_username: String,
+    #[serde(default)]
+    number_viewers: u64,
+    optional_message: Option<String>,
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct Event {
@@ -68,6 +585,7 @@ pub struct Event {
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct Data {
+    id: String,
     content: String,
     sender: MsgSender,
     created_at: DateTime<Utc>,
@@ -79,4 +597,401 @@ struct MsgSender {
     id: i64,
     username: String,
     slug: String,
+    identity: Option<Identity>,
+}
+
+impl MsgSender {
+    /// author() reads the roles from the badges, every badge is kept, like `og` or `sub_gifter`
+    fn author(self) -> Author {
+        let mut author = Author {
+            id: Some(self.id.to_string()),
+            name: self.username,
+            ..Author::default()
+        };
+        for badge in self
+            .identity
+            .map(|identity| identity.badges)
+            .unwrap_or_default()
+        {
+            if let Some(role) = Role::from_name(&badge.badge_type) {
+                author.add_role(role);
+            }
+            author.badges.push(Badge {
+                name: badge.text,
+                image_url: None,
+            });
+        }
+        author
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct Identity {
+    color: Option<String>,
+    #[serde(default)]
+    badges: Vec<KickBadge>,
+}
+
+/// `{"type": "subscriber", "text": "Subscriber", "count": 3}`
+#[allow(dead_code)]
+#[derive(Debug, Deserialize)]
+struct KickBadge {
+    #[serde(rename = "type")]
+    badge_type: String,
+    text: String,
+    count: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex as StdMutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    const CHANNEL: &str = "chatrooms.668.v2";
+
+    fn sent_text(action: PusherAction) -> String {
+        match action {
+            PusherAction::Send(Message::Text(text)) => text,
+            other => panic!("expected a text frame to send, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn connection_established() {
+        // `data` is a JSON encoded string
+        let event = PusherEvent::parse(
+            r#"{"event":"pusher:connection_established","data":"{\"socket_id\":\"1234.5678\",\"activity_timeout\":30}"}"#,
+        )
+        .unwrap();
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        let subscribe = sent_text(pusher.on_event(event).unwrap());
+
+        assert_eq!(pusher.state, PusherState::Subscribing);
+        assert_eq!(pusher.activity_timeout, Duration::from_secs(30));
+        let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+        assert_eq!(subscribe["event"], "pusher:subscribe");
+        assert_eq!(subscribe["data"]["channel"], CHANNEL);
+    }
+
+    #[test]
+    fn connection_established_without_activity_timeout() {
+        let event = PusherEvent::parse(
+            r#"{"event":"pusher:connection_established","data":{"socket_id":"1234.5678"}}"#,
+        )
+        .unwrap();
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        pusher.on_event(event).unwrap();
+        assert_eq!(pusher.activity_timeout, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn subscription_succeeded() {
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        let other = PusherEvent::parse(
+            r#"{"event":"pusher_internal:subscription_succeeded","data":"{}","channel":"channel.1"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            pusher.on_event(other).unwrap(),
+            PusherAction::None
+        ));
+        assert_eq!(pusher.state, PusherState::Connecting);
+
+        let event = PusherEvent::parse(&format!(
+            r#"{{"event":"pusher_internal:subscription_succeeded","data":"{{}}","channel":"{}"}}"#,
+            CHANNEL
+        ))
+        .unwrap();
+        assert!(matches!(
+            pusher.on_event(event).unwrap(),
+            PusherAction::None
+        ));
+        assert_eq!(pusher.state, PusherState::Subscribed);
+    }
+
+    #[test]
+    fn subscription_error() {
+        let event = PusherEvent::parse(
+            r#"{"event":"pusher:subscription_error","data":{"type":"AuthError","status":401},"channel":"private-x"}"#,
+        )
+        .unwrap();
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        assert!(pusher.on_event(event).is_err());
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        pusher.awaiting_pong = true;
+        let ping = PusherEvent::parse(r#"{"event":"pusher:ping","data":{}}"#).unwrap();
+        let pong = sent_text(pusher.on_event(ping).unwrap());
+        assert_eq!(pong, r#"{"event":"pusher:pong","data":{}}"#);
+        assert!(!pusher.awaiting_pong);
+
+        pusher.awaiting_pong = true;
+        let pong = PusherEvent::parse(r#"{"event":"pusher:pong","data":"{}"}"#).unwrap();
+        assert!(matches!(pusher.on_event(pong).unwrap(), PusherAction::None));
+        assert!(!pusher.awaiting_pong);
+    }
+
+    #[test]
+    fn pusher_error() {
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+
+        // 4000-4299 close the connection
+        let closing = PusherEvent::parse(
+            r#"{"event":"pusher:error","data":{"code":4201,"message":"Pong reply not received"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            closing,
+            PusherEvent::Error {
+                code: Some(4201),
+                ..
+            }
+        ));
+        assert!(pusher.on_event(closing).is_err());
+
+        let other = PusherEvent::parse(
+            r#"{"event":"pusher:error","data":"{\"code\":null,\"message\":\"unknown event\"}"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            pusher.on_event(other).unwrap(),
+            PusherAction::None
+        ));
+    }
+
+    #[test]
+    fn unknown_event() {
+        let event = PusherEvent::parse(
+            r#"{"event":"App\\Events\\PollUpdateEvent","data":"{\"poll\":{}}","channel":"chatrooms.668.v2"}"#,
+        )
+        .unwrap();
+        let PusherEvent::Other { event, channel } = &event else {
+            panic!("expected an unknown event, got {:?}", event);
+        };
+        assert_eq!(event, r"App\Events\PollUpdateEvent");
+        assert_eq!(channel.as_deref(), Some(CHANNEL));
+
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        let event = PusherEvent::parse(r#"{"event":"client-whatever"}"#).unwrap();
+        assert!(matches!(
+            pusher.on_event(event).unwrap(),
+            PusherAction::None
+        ));
+    }
+
+    #[test]
+    fn double_encoded_chat_message() {
+        let raw_frame = r##"{"event":"App\\Events\\ChatMessageEvent","data":"{\"id\":\"9c2a0e1b\",\"chatroom_id\":668,\"content\":\"hi [emote:37226:KEKW]\",\"type\":\"message\",\"created_at\":\"2023-11-22T10:00:00+00:00\",\"sender\":{\"id\":42,\"username\":\"Viewer\",\"slug\":\"viewer\",\"identity\":{\"color\":\"#FF0000\",\"badges\":[{\"type\":\"moderator\",\"text\":\"Moderator\"}]}}}","channel":"chatrooms.668.v2"}"##;
+        let event = PusherEvent::parse(raw_frame).unwrap();
+        let mut pusher = PusherClient::new(CHANNEL.to_string());
+        let PusherAction::Kick(event) = pusher.on_event(event).unwrap() else {
+            panic!("expected a kick event");
+        };
+        let ChatEvent::Msg(msg) = event.into_chat_event("xqc", raw_frame) else {
+            panic!("expected a chat message");
+        };
+        assert_eq!(msg.id.as_deref(), Some("9c2a0e1b"));
+        assert_eq!(msg.author.name, "Viewer");
+        assert_eq!(msg.msg_text, "hi :KEKW:");
+        assert_eq!(msg.fragments.len(), 2);
+    }
+
+    fn banned(data: &str) -> String {
+        let raw_frame = format!(
+            r#"{{"event":"App\\Events\\UserBannedEvent","data":{},"channel":"chatrooms.668.v2"}}"#,
+            serde_json::to_string(data).unwrap()
+        );
+        let Ok(PusherEvent::Kick(event)) = PusherEvent::parse(&raw_frame) else {
+            panic!("expected a kick event");
+        };
+        let ChatEvent::Notice(notice) = event.into_chat_event("xqc", &raw_frame) else {
+            panic!("expected a notice");
+        };
+        notice.kind.describe()
+    }
+
+    #[test]
+    fn user_banned() {
+        let user = r#""user":{"id":1,"username":"Bob","slug":"bob"},"banned_by":{"id":2,"username":"Mod","slug":"mod"}"#;
+        assert_eq!(
+            banned(&format!(r#"{{"id":"b1",{},"permanent":true}}"#, user)),
+            "Bob was permanently banned by Mod"
+        );
+        // not permanent, but kick didn't send when it ends
+        assert_eq!(
+            banned(&format!(r#"{{"id":"b2",{},"permanent":false}}"#, user)),
+            "Bob was banned by Mod"
+        );
+        assert!(banned(&format!(
+            r#"{{"id":"b3",{},"permanent":false,"expires_at":"2023-11-22T10:10:00+00:00"}}"#,
+            user
+        ))
+        .starts_with("Bob was banned by Mod until "));
+    }
+
+    #[test]
+    fn invalid_frames() {
+        assert!(PusherEvent::parse("not json").is_err());
+        // a known event whose double encoded data is broken
+        assert!(PusherEvent::parse(
+            r#"{"event":"pusher:connection_established","data":"{\"socket_id\":"}"#
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resubscribes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pusher_url = format!("ws://{}/app/key?protocol=7", listener.local_addr().unwrap());
+        let (tx, _rx) = mpsc::channel(16);
+        let health = Health::default();
+        // every delay is between half and all of its step, a factor of 4 keeps the ranges apart
+        let backoff = Backoff::new(Duration::from_millis(40), Duration::from_secs(5), 4);
+        let supervisor = tokio::spawn({
+            let health = health.clone();
+            async move {
+                supervise("kick test", &health, backoff, || {
+                    connect_pusher(tx.clone(), "xqc".to_string(), pusher_url.clone(), 668)
+                })
+                .await
+            }
+        });
+
+        let mut accepted = vec![];
+        for _ in 0..4 {
+            let (stream, _) = timeout(Duration::from_secs(5), listener.accept())
+                .await
+                .expect("kick didn't reconnect")
+                .unwrap();
+            accepted.push(Instant::now());
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            socket
+                .send(Message::Text(
+                    r#"{"event":"pusher:connection_established","data":"{\"socket_id\":\"1.2\"}"}"#
+                        .to_string(),
+                ))
+                .await
+                .unwrap();
+            let subscribe = socket.next().await.unwrap().unwrap();
+            let subscribe: Value = serde_json::from_str(subscribe.to_text().unwrap()).unwrap();
+            assert_eq!(subscribe["event"], "pusher:subscribe");
+            assert_eq!(subscribe["data"]["channel"], CHANNEL);
+            // gone without a close frame, like a dropped connection
+            drop(socket);
+        }
+        supervisor.abort();
+
+        let gaps: Vec<_> = accepted.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(gaps[0] >= Duration::from_millis(20), "{:?}", gaps);
+        assert!(gaps[1] >= Duration::from_millis(80), "{:?}", gaps);
+        assert!(gaps[2] >= Duration::from_millis(320), "{:?}", gaps);
+        assert!(gaps.windows(2).all(|w| w[0] < w[1]), "{:?}", gaps);
+        assert!(health.state().restarts >= 3);
+    }
+
+    /// mock_api() serves `/api/v2/channels/{slug}` with the chatroom ids of `channels`,
+    /// and returns its url and the paths it was asked for
+    async fn mock_api(
+        channels: &'static [(&'static str, u64)],
+    ) -> (String, Arc<StdMutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(StdMutex::new(vec![]));
+        let paths = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = vec![0; 4096];
+                let read = stream.read(&mut head).await.unwrap();
+                let head = String::from_utf8_lossy(&head[..read]).to_string();
+                let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+                paths.lock().unwrap().push(path.clone());
+
+                let chatroom = channels
+                    .iter()
+                    .find(|(slug, _)| path == format!("/api/v2/channels/{}", slug));
+                let response = match chatroom {
+                    Some((slug, id)) => {
+                        let body = format!(
+                            r#"{{"id":1,"slug":"{}","chatroom":{{"id":{},"channel_id":1}}}}"#,
+                            slug, id
+                        );
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+                    }
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (api_url, requests)
+    }
+
+    #[tokio::test]
+    async fn chatroom_id_cache() {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "chat-rs-kick-{}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let cache_path = cache_dir.join("kick_chatrooms.json");
+        let (kick_api, kick_requests) = mock_api(&[("xqc", 668), ("roshtein", 4598)]).await;
+        let (other_api, other_requests) = mock_api(&[("xqc", 12)]).await;
+
+        assert_eq!(
+            get_cached_chatroom_id(&cache_path, &kick_api, "xqc")
+                .await
+                .unwrap(),
+            668
+        );
+        assert_eq!(*kick_requests.lock().unwrap(), ["/api/v2/channels/xqc"]);
+
+        // the second lookup is read from the cache
+        assert_eq!(
+            get_cached_chatroom_id(&cache_path, &kick_api, "xqc")
+                .await
+                .unwrap(),
+            668
+        );
+        assert_eq!(kick_requests.lock().unwrap().len(), 1);
+
+        // the same slug on another api is looked up there
+        assert_eq!(
+            get_cached_chatroom_id(&cache_path, &other_api, "xqc")
+                .await
+                .unwrap(),
+            12
+        );
+        assert_eq!(*other_requests.lock().unwrap(), ["/api/v2/channels/xqc"]);
+
+        // channels looked up at the same time all end up in the cache
+        let (roshtein, missing) = tokio::join!(
+            get_cached_chatroom_id(&cache_path, &kick_api, "roshtein"),
+            get_cached_chatroom_id(&cache_path, &kick_api, "missing"),
+        );
+        assert_eq!(roshtein.unwrap(), 4598);
+        assert!(matches!(missing, Err(SourceError::Http(_))));
+
+        let cache = read_chatroom_cache(&cache_path).await;
+        let kick_api = kick_api.trim_end_matches('/');
+        let other_api = other_api.trim_end_matches('/');
+        assert_eq!(cache[kick_api]["xqc"], 668);
+        assert_eq!(cache[kick_api]["roshtein"], 4598);
+        assert!(!cache[kick_api].contains_key("missing"));
+        assert_eq!(cache[other_api]["xqc"], 12);
+        assert!(!cache_path.with_extension("json.tmp").exists());
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
 }
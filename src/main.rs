@@ -1,16 +1,31 @@
 use clap::{Parser, Subcommand};
-use log::info;
+use futures_util::StreamExt;
+use log::{debug, error, info};
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::sync::broadcast;
 
+pub mod config;
 pub mod dgg;
+pub mod irc;
 pub mod kick;
 pub mod printer;
+pub mod rumble;
+pub mod source;
+pub mod supervisor;
+pub mod twitch;
 pub mod types;
 pub mod utils;
 pub mod web_ui;
+pub mod websocket;
 pub mod youtube;
 
-use types::ChatMsg;
+use config::{Config, SourceConfig};
+use source::{Health, SourceContext, SourceRegistry};
+use types::ChatEvent;
+
+/// how often the health of the sources is logged
+const HEALTH_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -26,6 +41,57 @@ struct Args {
     web: bool,
     #[arg(long,default_value_t = false,action = clap::ArgAction::SetTrue)]
     all: bool,
+    /// print the platforms chat can be read from and exit
+    #[arg(long,default_value_t = false,action = clap::ArgAction::SetTrue)]
+    list_sources: bool,
+    /// path to a TOML config file listing the chat sources
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// a chat as `platform:target`, like `kick:xqc` or `youtube:replay:dQw4w9WgXcQ`,
+    /// can be given multiple times, --list-sources prints the target of every platform
+    #[arg(long, value_parser = parse_source)]
+    source: Vec<SourceConfig>,
+    /// the flags from before `--source`, still accepted as `--source dgg:{url}` and so on
+    #[arg(long, hide = true, value_parser = |url: &str| parse_source(&format!("dgg:{}", url)))]
+    dgg_url: Vec<SourceConfig>,
+    #[arg(long, hide = true, value_parser = |channel: &str| parse_source(&format!("kick:{}", channel)))]
+    kick_channel: Vec<SourceConfig>,
+    #[arg(long, hide = true, value_parser = |channel: &str| parse_source(&format!("youtube:channel:{}", channel)))]
+    youtube_channel: Vec<SourceConfig>,
+}
+
+/// parse_source() maps `--source` to a config with the factory of its platform
+fn parse_source(source: &str) -> Result<SourceConfig, String> {
+    SourceRegistry::default().from_cli(source)
+}
+
+impl Args {
+    /// combines the sources from the config file with the ones given on the command line,
+    /// `--dgg`, `--kick`, `--youtube` and `--all` add the default chat of a platform that has none
+    fn sources(&self, config: Config, registry: &SourceRegistry) -> Vec<SourceConfig> {
+        let mut sources = config.sources;
+        sources.extend(self.source.iter().cloned());
+        sources.extend(self.dgg_url.iter().cloned());
+        sources.extend(self.kick_channel.iter().cloned());
+        sources.extend(self.youtube_channel.iter().cloned());
+
+        let has_platform = |platform: &str| sources.iter().any(|s| s.platform() == platform);
+        let defaults: Vec<SourceConfig> = [
+            ("dgg", self.dgg),
+            ("kick", self.kick),
+            ("youtube", self.youtube),
+        ]
+        .into_iter()
+        .filter(|(platform, enabled)| (*enabled || self.all) && !has_platform(platform))
+        .map(|(platform, _)| {
+            registry
+                .from_cli(platform)
+                .expect("the platform has a default chat")
+        })
+        .collect();
+        sources.extend(defaults);
+        sources
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -38,41 +104,59 @@ enum Command {
 
 #[tokio::main]
 async fn main() {
-    let youtube_channel_name = "destiny";
-
     let rust_log = std::env::var("RUST_LOG").unwrap_or("".to_string());
     println!("`RUST_LOG` env var is `{}`", rust_log);
     env_logger::init();
     let args = Args::parse();
+    let registry = SourceRegistry::default();
+
+    if args.list_sources {
+        for factory in registry.factories() {
+            println!("{:<10}{}", factory.platform, factory.description);
+            println!("{:<10}target: {}", "", factory.target);
+        }
+        return;
+    }
 
-    let (tx, rx) = broadcast::channel::<ChatMsg>(100);
+    let (tx, rx) = broadcast::channel::<ChatEvent>(100);
 
     let mut join_handles = vec![];
 
-    if args.dgg || args.all {
-        info!("Staring dgg thread");
-        let dgg_tx = tx.clone();
-        let join_handel = tokio::spawn(dgg::main(dgg_tx));
-        join_handles.push(join_handel);
-    }
-    if args.kick || args.all {
-        info!("Staring kick thread");
-        let kick_tx = tx.clone();
-        let join_handel = tokio::spawn(kick::main(kick_tx));
-        join_handles.push(join_handel);
-    }
+    let config = match &args.config {
+        Some(path) => Config::load(path).expect("Can't load config"),
+        None => Config::default(),
+    };
+
+    let mut context = SourceContext::default();
+    let mut healths = vec![];
 
-    if args.youtube || args.all {
-        info!("Staring youtube thread");
-        let youtube_tx = tx.clone();
-        let join_handel = tokio::spawn(youtube::scraper::main(youtube_tx, youtube_channel_name));
+    for source_config in args.sources(config, &registry) {
+        let Some(source) = registry.build(&source_config, &mut context) else {
+            error!("No source for {}", source_config.name());
+            continue;
+        };
+        let name = source.name();
+        info!("Staring {} thread", name);
+        let health = Health::default();
+        healths.push((name, health.clone()));
+
+        let source_tx = tx.clone();
+        let mut events = source.run(health);
+        let join_handel = tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                source_tx.send(event).unwrap();
+            }
+        });
         join_handles.push(join_handel);
     }
+    let dgg_sender = context.dgg_sender;
+
+    tokio::spawn(log_health(healths));
 
     if args.web || args.all {
         info!("Staring web ui thread");
         let web_rx = rx.resubscribe();
-        let join_handel = tokio::spawn(web_ui::main(web_rx));
+        let join_handel = tokio::spawn(web_ui::main(web_rx, dgg_sender.clone()));
         join_handles.push(join_handel);
     }
 
@@ -81,9 +165,71 @@ async fn main() {
         let printer_rx = rx.resubscribe();
         let join_handel = tokio::spawn(printer::main(printer_rx));
         join_handles.push(join_handel);
+
+        if let Some(dgg_sender) = dgg_sender.clone() {
+            info!("Staring terminal reply thread");
+            let join_handel = tokio::spawn(printer::read_replies(dgg_sender));
+            join_handles.push(join_handel);
+        }
     }
 
     for join_handel in join_handles {
         join_handel.await.unwrap();
     }
 }
+
+/// log_health() logs how every source is doing once a minute
+async fn log_health(healths: Vec<(String, Health)>) {
+    loop {
+        tokio::time::sleep(HEALTH_LOG_INTERVAL).await;
+        for (name, health) in &healths {
+            let state = health.state();
+            debug!(
+                "{} is {:?}, {} events, last at {:?}, {} restarts, last error {:?}",
+                name,
+                state.status,
+                state.events,
+                state.last_event,
+                state.restarts,
+                state.last_error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(args: &[&str]) -> Vec<String> {
+        let args = Args::try_parse_from([&["chat-rs"], args].concat()).unwrap();
+        args.sources(Config::default(), &SourceRegistry::default())
+            .iter()
+            .map(SourceConfig::name)
+            .collect()
+    }
+
+    #[test]
+    fn old_flags_are_sources() {
+        assert_eq!(
+            sources(&[
+                "--dgg-url",
+                "wss://chat.example.com/ws",
+                "--kick-channel",
+                "xqc",
+                "--youtube-channel",
+                "destiny",
+            ]),
+            sources(&[
+                "--source",
+                "dgg:wss://chat.example.com/ws",
+                "--source",
+                "kick:xqc",
+                "--source",
+                "youtube:channel:destiny",
+            ])
+        );
+        // a chat given the old way isn't doubled by the default of its platform
+        assert_eq!(sources(&["--kick", "--kick-channel", "xqc"]).len(), 1);
+    }
+}
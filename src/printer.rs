@@ -1,10 +1,17 @@
-use crate::types::ChatMsg;
-use log::{warn};
+use crate::dgg::DggSender;
+use crate::types::{ChatEvent, NoticeKind};
+use colored::Colorize;
+use log::{info, warn};
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::broadcast::Receiver;
 use tokio::time::sleep;
 
-pub async fn main(mut rx: Receiver<ChatMsg>) {
+/// how many messages and paid messages are remembered so a later deletion can show what was deleted
+const RECENT_MSGS_CAPACITY: usize = 1000;
+
+pub async fn main(mut rx: Receiver<ChatEvent>) {
     // for some fucking reason this is needed
     loop {
         if rx.is_empty() {
@@ -14,12 +21,98 @@ pub async fn main(mut rx: Receiver<ChatMsg>) {
         }
     }
 
+    let mut recent_msgs = RecentMsgs::default();
     loop {
         match rx.recv().await {
-            Ok(msg) => {
+            Ok(ChatEvent::Msg(msg)) => {
                 println!("{}", msg.cli_format());
+                recent_msgs.insert(msg.into());
+            }
+            Ok(ChatEvent::Notice(notice)) => {
+                let target_msg = notice
+                    .target_msg_id()
+                    .and_then(|msg_id| recent_msgs.get(&notice.source_name(), msg_id));
+                match (&notice.kind, target_msg) {
+                    (NoticeKind::MsgReplaced { msg, .. }, Some(_)) => {
+                        println!("{} {}", msg.cli_format(), "(edited)".dimmed());
+                        recent_msgs.insert(ChatEvent::Msg(*msg.clone()));
+                    }
+                    (NoticeKind::MsgReplaced { msg, .. }, None) => {
+                        println!("{}", msg.cli_format());
+                        recent_msgs.insert(ChatEvent::Msg(*msg.clone()));
+                    }
+                    (_, Some(target)) => {
+                        println!(
+                            "{} {}",
+                            target.cli_format().strikethrough(),
+                            "(deleted)".red()
+                        )
+                    }
+                    (_, None) => {
+                        println!("{}", notice.cli_format());
+                        recent_msgs.insert(notice.into());
+                    }
+                }
             }
             Err(recv_error) => warn!("Printer got recv error, {}", recv_error),
         }
     }
 }
+
+/// read_replies() sends every line typed in the terminal to the dgg chat
+pub async fn read_replies(dgg: DggSender) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if let Err(err) = dgg.send_message(line).await {
+                    warn!("Can't send reply, {}", err);
+                }
+            }
+            Ok(None) => {
+                info!("stdin closed, no more replies");
+                return;
+            }
+            Err(err) => {
+                warn!("Can't read reply, {}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// The last few messages and paid messages that had an id, keyed by source name and id
+#[derive(Default)]
+struct RecentMsgs {
+    msgs: HashMap<(String, String), ChatEvent>,
+    order: VecDeque<(String, String)>,
+}
+
+impl RecentMsgs {
+    fn insert(&mut self, chat_event: ChatEvent) {
+        let key = match &chat_event {
+            ChatEvent::Msg(msg) => msg.id.clone().map(|id| (msg.source_name(), id)),
+            ChatEvent::Notice(notice) => notice
+                .msg_id()
+                .map(|id| (notice.source_name(), id.to_string())),
+        };
+        let Some(key) = key else {
+            return;
+        };
+        if self.order.len() >= RECENT_MSGS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.msgs.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.msgs.insert(key, chat_event);
+    }
+
+    fn get(&self, source_name: &str, id: &str) -> Option<&ChatEvent> {
+        self.msgs.get(&(source_name.to_string(), id.to_string()))
+    }
+}
@@ -0,0 +1,471 @@
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{
+    Author, Badge, ChatEvent, ChatLocation, ChatMsg, ChatNotice, Fragment, NoticeKind, Role,
+};
+use crate::utils::string_or_number;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+
+pub async fn main(tx: Sender<ChatEvent>, stream: String, chat_url: String, health: Health) {
+    let name = format!("rumble {}", stream);
+    let client = reqwest::Client::new();
+    supervise(&name, &health, Backoff::default(), || {
+        connect(tx.clone(), client.clone(), stream.clone(), chat_url.clone())
+    })
+    .await
+}
+
+async fn connect(
+    tx: Sender<ChatEvent>,
+    client: reqwest::Client,
+    stream: String,
+    chat_url: String,
+) -> Result<(), SourceError> {
+    let chat_id = get_chat_id(&client, &stream).await?;
+
+    let url = format!("{}/{}/stream", chat_url.trim_end_matches('/'), chat_id);
+    debug!("Connecting to rumble chat stream {}", url);
+    let mut res = client
+        .get(&url)
+        .header("Accept", "text/event-stream")
+        .send()
+        .await?
+        .error_for_status()?;
+    info!("connected to rumble chat {}", chat_id);
+
+    let mut events = SseBuffer::default();
+    let mut users = HashMap::new();
+    while let Some(chunk) = res.chunk().await? {
+        for data in events.push(&chunk) {
+            debug!("{}", data);
+            let event = match serde_json::from_str::<RumbleEvent>(&data) {
+                Ok(event) => event,
+                Err(err) => {
+                    warn!("Can't parse rumble event `{}`, {}", data, err);
+                    continue;
+                }
+            };
+            for chat_event in event.into_chat_events(&chat_id, &mut users, &data) {
+                tx.send(chat_event).await?;
+            }
+        }
+    }
+    Err(SourceError::Closed)
+}
+
+/// get_chat_id() accepts the numeric chat id, the base 36 embed id like `v5c7u2x`,
+/// or the url of a rumble video, whose page links the embed id
+async fn get_chat_id(client: &reqwest::Client, stream: &str) -> Result<String, SourceError> {
+    let stream = stream.trim();
+    if !stream.is_empty() && stream.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(stream.to_string());
+    }
+    if let Some(chat_id) = parse_embed_id(stream) {
+        return Ok(chat_id);
+    }
+
+    let page = client
+        .get(stream)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let marker = "https://rumble.com/embed/";
+    page.match_indices(marker)
+        .find_map(|(start, _)| {
+            let rest = &page[start + marker.len()..];
+            let end = rest.find(['/', '"', '?']).unwrap_or(rest.len());
+            parse_embed_id(&rest[..end])
+        })
+        .ok_or(SourceError::Other(format!(
+            "no rumble embed id on {}",
+            stream
+        )))
+}
+
+/// parse_embed_id() turns an embed id like `v5c7u2x` into the numeric chat id, the id after `v` is base 36
+fn parse_embed_id(embed_id: &str) -> Option<String> {
+    let id = embed_id.strip_prefix('v')?;
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase())
+    {
+        return None;
+    }
+    u64::from_str_radix(id, 36).ok().map(|id| id.to_string())
+}
+
+/// Collects the chunks of a server sent events stream into the `data` of whole events,
+/// the bytes are only decoded once an event is complete so a chunk can end anywhere,
+/// even inside a character or between the `\r` and `\n` of a line ending
+#[derive(Debug, Default)]
+struct SseBuffer {
+    buffer: Vec<u8>,
+}
+
+impl SseBuffer {
+    /// push() adds a chunk and returns the data of every event it completed,
+    /// an event ends with an empty line and its `data:` lines are joined with newlines
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        while let Some(end) = event_end(&self.buffer) {
+            let event: Vec<u8> = self.buffer.drain(..end).collect();
+            let event = String::from_utf8_lossy(&event);
+            let data: Vec<&str> = event
+                .split(['\r', '\n'])
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+            if !data.is_empty() {
+                events.push(data.join("\n"));
+            }
+        }
+        events
+    }
+}
+
+/// event_end() is the index just past the empty line that ends the first event,
+/// lines end with `\r\n`, `\n` or `\r`
+fn event_end(buffer: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < buffer.len() {
+        let line_end = match buffer[i] {
+            b'\n' => i + 1,
+            // a `\n` can still follow in the next chunk
+            b'\r' if i + 1 == buffer.len() => return None,
+            b'\r' if buffer[i + 1] == b'\n' => i + 2,
+            b'\r' => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        if i == line_start {
+            return Some(line_end);
+        }
+        line_start = line_end;
+        i = line_end;
+    }
+    None
+}
+
+/// `{"type": "init" | "messages" | "delete_messages" | .., "data": {..}}`
+#[derive(Debug, Deserialize)]
+struct RumbleEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(default)]
+    data: RumbleData,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RumbleData {
+    #[serde(default)]
+    messages: Vec<RumbleMessage>,
+    /// the authors of `messages`, only sent the first time they show up
+    #[serde(default)]
+    users: Vec<RumbleUser>,
+    /// for `delete_messages`
+    #[serde(default)]
+    message_ids: Vec<MsgId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MsgId(#[serde(with = "string_or_number")] String);
+
+#[derive(Debug, Deserialize)]
+struct RumbleMessage {
+    #[serde(with = "string_or_number")]
+    id: String,
+    #[serde(with = "string_or_number")]
+    user_id: String,
+    time: DateTime<Utc>,
+    #[serde(default)]
+    text: String,
+    rant: Option<Rant>,
+}
+
+/// A paid message, shown at the top of the chat for a while
+#[derive(Debug, Deserialize)]
+struct Rant {
+    price_cents: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RumbleUser {
+    #[serde(with = "string_or_number")]
+    id: String,
+    username: String,
+    #[serde(rename = "image.1")]
+    image: Option<String>,
+    #[serde(default)]
+    badges: Vec<String>,
+}
+
+impl RumbleUser {
+    fn author(&self) -> Author {
+        let mut author = Author {
+            id: Some(self.id.clone()),
+            name: self.username.clone(),
+            avatar_url: self.image.clone(),
+            ..Author::default()
+        };
+        for badge in &self.badges {
+            let role = match badge.as_str() {
+                "premium" | "locals" | "locals_supporter" | "recurring_subscription" => {
+                    Some(Role::Subscriber)
+                }
+                badge => Role::from_name(badge),
+            };
+            if let Some(role) = role {
+                author.add_role(role);
+            }
+            author.badges.push(Badge {
+                name: badge.clone(),
+                image_url: None,
+            });
+        }
+        author
+    }
+}
+
+impl RumbleEvent {
+    fn into_chat_events(
+        self,
+        channel: &str,
+        users: &mut HashMap<String, RumbleUser>,
+        raw_msg_text: &str,
+    ) -> Vec<ChatEvent> {
+        for user in self.data.users {
+            users.insert(user.id.clone(), user);
+        }
+
+        match self.event_type.as_str() {
+            // `init` has the recent messages, `messages` the new ones
+            "init" | "messages" => self
+                .data
+                .messages
+                .into_iter()
+                .map(|msg| {
+                    let author = users
+                        .get(&msg.user_id)
+                        .map(RumbleUser::author)
+                        .unwrap_or_else(|| Author {
+                            id: Some(msg.user_id.clone()),
+                            name: msg.user_id.clone(),
+                            ..Author::default()
+                        });
+                    msg.into_chat_event(channel, author, raw_msg_text)
+                })
+                .collect(),
+            "delete_messages" => self
+                .data
+                .message_ids
+                .into_iter()
+                .map(|MsgId(msg_id)| {
+                    ChatNotice {
+                        location: ChatLocation::Rumble,
+                        channel: channel.to_string(),
+                        timestamp: Utc::now(),
+                        stream_offset: None,
+                        kind: NoticeKind::MsgDeleted { msg_id },
+                        raw_full_msg: raw_msg_text.to_string(),
+                    }
+                    .into()
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// rants are always paid in US dollars
+const RANT_CURRENCY: &str = "$";
+
+/// The header and body colours of the rant levels, by the lowest price in cents of each level
+const RANT_COLORS: [(u64, u32, u32); 7] = [
+    (50_000, 0xffd00000, 0xffe62117),
+    (10_000, 0xffc2185b, 0xffe91e63),
+    (5_000, 0xffe65100, 0xfff57c00),
+    (2_000, 0xffffb300, 0xffffca28),
+    (1_000, 0xff00bfa5, 0xff1de9b6),
+    (500, 0xff00b8d4, 0xff00e5ff),
+    (0, 0xff1565c0, 0xff1e88e5),
+];
+
+/// rant_colors() returns the header and body colours of the level a rant of `price_cents` is in
+fn rant_colors(price_cents: u64) -> (u32, u32) {
+    RANT_COLORS
+        .iter()
+        .find(|(min_cents, _, _)| price_cents >= *min_cents)
+        .map(|(_, header_color, body_color)| (*header_color, *body_color))
+        .unwrap_or_default()
+}
+
+impl RumbleMessage {
+    fn into_chat_event(self, channel: &str, author: Author, raw_msg_text: &str) -> ChatEvent {
+        let Some(rant) = self.rant else {
+            return ChatMsg {
+                id: Some(self.id),
+                location: ChatLocation::Rumble,
+                channel: channel.to_string(),
+                timestamp: self.time,
+                stream_offset: None,
+                fragments: Fragment::parse_text(&self.text),
+                msg_text: self.text,
+                author,
+                raw_full_msg: raw_msg_text.to_string(),
+            }
+            .into();
+        };
+
+        let amount = rant.price_cents as f64 / 100.0;
+        let (header_color, body_color) = rant_colors(rant.price_cents);
+        ChatNotice {
+            location: ChatLocation::Rumble,
+            channel: channel.to_string(),
+            timestamp: self.time,
+            stream_offset: None,
+            kind: NoticeKind::Paid {
+                msg_id: Some(self.id),
+                author: author.name,
+                amount_text: format!("{}{:.2}", RANT_CURRENCY, amount),
+                amount,
+                currency: RANT_CURRENCY.to_string(),
+                msg_text: Some(self.text),
+                sticker: None,
+                header_color: Some(header_color),
+                body_color: Some(body_color),
+            },
+            raw_full_msg: raw_msg_text.to_string(),
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sse_events() {
+        let mut events = SseBuffer::default();
+        assert_eq!(
+            events.push(b"data: {\"a\":1}\n\nevent: ping\n\ndata: x\ndata:y\n\ndata: z"),
+            ["{\"a\":1}", "x\ny"]
+        );
+        assert_eq!(events.push(b"\n"), Vec::<String>::new());
+        assert_eq!(events.push(b"\n"), ["z"]);
+    }
+
+    #[test]
+    fn sse_crlf_split_across_chunks() {
+        let mut events = SseBuffer::default();
+        assert!(events.push(b"data: a\r\n\r").is_empty());
+        // the last `\r` could still be followed by a `\n`
+        assert_eq!(events.push(b"\ndata: b\r\r"), ["a"]);
+        assert_eq!(events.push(b"data: c\r\n\r\n"), ["b", "c"]);
+        assert!(events.buffer.is_empty());
+    }
+
+    #[test]
+    fn sse_char_split_across_chunks() {
+        let event = "data: caf\u{e9} \u{1f600}\n\n".as_bytes();
+        let mut events = SseBuffer::default();
+        let mut data = vec![];
+        for byte in event {
+            data.extend(events.push(&[*byte]));
+        }
+        assert_eq!(data, ["caf\u{e9} \u{1f600}"]);
+    }
+
+    #[test]
+    fn embed_ids() {
+        assert_eq!(parse_embed_id("v5c7u2x").as_deref(), Some("322851849"));
+        assert_eq!(parse_embed_id("v3ufx9b").as_deref(), Some("232529951"));
+        for embed_id in ["5c7u2x", "v", "v5C7U2X", "v5c-7u2x", ""] {
+            assert_eq!(parse_embed_id(embed_id), None, "{}", embed_id);
+        }
+    }
+
+    const INIT: &str = r##"{"type":"init","data":{"messages":[{"id":"1346698047936061703","time":"2023-11-22T10:00:00+00:00","user_id":"88707682","text":"hello @bob"},{"id":"1346698047936061704","time":"2023-11-22T10:00:01+00:00","user_id":"99","text":"who am i"}],"users":[{"id":"88707682","username":"someone","link":"/user/someone","is_follower":false,"image.1":"https://ak2.rmbl.ws/z0/avatar.jpeg","color":"#f54fd1","badges":["premium","moderator"]}],"channels":[[]],"config":{"message_length_max":200}},"request_id":"fc3c8d5a"}"##;
+    const MESSAGES: &str = r#"{"type":"messages","data":{"messages":[{"id":1346698047936061705,"time":"2023-11-22T10:00:02+00:00","user_id":88707682,"text":"again"},{"id":"1346698047936061706","time":"2023-11-22T10:00:03+00:00","user_id":"88707682","text":"take my money","rant":{"price_cents":2500,"duration":300,"expires_on":"2023-11-22T10:05:03+00:00"}}],"users":[],"channels":[[]]},"request_id":"fc3c8d5b"}"#;
+    const DELETE_MESSAGES: &str = r#"{"type":"delete_messages","data":{"message_ids":["1346698047936061703",1346698047936061705]},"request_id":"fc3c8d5c"}"#;
+
+    fn chat_events(users: &mut HashMap<String, RumbleUser>, data: &str) -> Vec<ChatEvent> {
+        let event: RumbleEvent = serde_json::from_str(data).unwrap();
+        event.into_chat_events("322851849", users, data)
+    }
+
+    #[test]
+    fn events() {
+        let mut users = HashMap::new();
+
+        let init = chat_events(&mut users, INIT);
+        let [ChatEvent::Msg(msg), ChatEvent::Msg(unknown)] = &init[..] else {
+            panic!("expected two messages, got {:?}", init);
+        };
+        assert_eq!(msg.id.as_deref(), Some("1346698047936061703"));
+        assert_eq!(msg.msg_text, "hello @bob");
+        assert_eq!(msg.fragments[1], Fragment::Mention("bob".to_string()));
+        assert_eq!(msg.author.name, "someone");
+        assert_eq!(
+            msg.author.avatar_url.as_deref(),
+            Some("https://ak2.rmbl.ws/z0/avatar.jpeg")
+        );
+        assert_eq!(msg.author.roles, [Role::Subscriber, Role::Moderator]);
+        assert_eq!(msg.raw_full_msg, INIT);
+        // users that weren't sent are shown by their id
+        assert_eq!(unknown.author.id.as_deref(), Some("99"));
+        assert_eq!(unknown.author.name, "99");
+
+        // later events only send the users that are new, the known ones come from the cache
+        let messages = chat_events(&mut users, MESSAGES);
+        let [ChatEvent::Msg(msg), ChatEvent::Notice(rant)] = &messages[..] else {
+            panic!("expected a message and a rant, got {:?}", messages);
+        };
+        assert_eq!(msg.id.as_deref(), Some("1346698047936061705"));
+        assert_eq!(msg.author.name, "someone");
+        assert!(matches!(
+            &rant.kind,
+            NoticeKind::Paid { author, amount_text, amount, currency, msg_text: Some(msg_text), .. }
+                if author == "someone" && amount_text == "$25.00" && *amount == 25.0
+                    && currency == "$" && msg_text == "take my money"
+        ));
+        assert_eq!(rant.background_color(), Some(0xffffb300));
+
+        let deleted = chat_events(&mut users, DELETE_MESSAGES);
+        let msg_ids: Vec<_> = deleted
+            .iter()
+            .map(|chat_event| match chat_event {
+                ChatEvent::Notice(ChatNotice {
+                    kind: NoticeKind::MsgDeleted { msg_id },
+                    ..
+                }) => msg_id.as_str(),
+                other => panic!("expected a deletion, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(msg_ids, ["1346698047936061703", "1346698047936061705"]);
+
+        let pong = r#"{"type":"pong","data":{}}"#;
+        assert!(chat_events(&mut users, pong).is_empty());
+    }
+
+    #[test]
+    fn rant_levels() {
+        assert_eq!(rant_colors(100), (0xff1565c0, 0xff1e88e5));
+        assert_eq!(rant_colors(499), (0xff1565c0, 0xff1e88e5));
+        assert_eq!(rant_colors(500), (0xff00b8d4, 0xff00e5ff));
+        assert_eq!(rant_colors(1_000), (0xff00bfa5, 0xff1de9b6));
+        assert_eq!(rant_colors(100_000), (0xffd00000, 0xffe62117));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::config::{self, IrcNetwork, SourceConfig, YoutubeTarget};
+use crate::dgg::{self, DggSender, Outgoing};
+use crate::types::ChatEvent;
+use crate::{irc, kick, rumble, twitch, websocket, youtube};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream};
+use futures_util::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, Sender};
+
+/// A platform the app can read chat from, built from one entry of the config
+pub trait ChatSource: Send {
+    /// the platform, like `kick`
+    fn platform(&self) -> &'static str;
+
+    /// a name for logs, like `kick xqc`
+    fn name(&self) -> String;
+
+    /// the config the source was built from
+    fn config(&self) -> &SourceConfig;
+
+    /// run() starts the source and streams its events,
+    /// it reconnects on its own and the stream ends once the source is done for good
+    fn run(self: Box<Self>, health: Health) -> BoxStream<'static, ChatEvent>;
+}
+
+/// How a running source is doing, shared between the source and whoever watches it
+#[derive(Debug, Clone, Default)]
+pub struct Health(Arc<Mutex<HealthState>>);
+
+#[derive(Debug, Clone, Default)]
+pub struct HealthState {
+    pub status: Status,
+    /// how many events the source sent
+    pub events: u64,
+    pub last_event: Option<DateTime<Utc>>,
+    /// how many times the source had to reconnect
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Status {
+    #[default]
+    Connecting,
+    /// connected and sending events
+    Running,
+    /// waiting out the backoff after an outage
+    Reconnecting,
+    /// the source is done for good, for example a finished replay
+    Finished,
+}
+
+impl Health {
+    pub fn state(&self) -> HealthState {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn connecting(&self) {
+        self.0.lock().unwrap().status = Status::Connecting;
+    }
+
+    pub fn reconnecting(&self, err: &dyn std::error::Error) {
+        let mut state = self.0.lock().unwrap();
+        state.status = Status::Reconnecting;
+        state.restarts += 1;
+        state.last_error = Some(err.to_string());
+    }
+
+    pub fn finished(&self) {
+        self.0.lock().unwrap().status = Status::Finished;
+    }
+
+    fn event(&self) {
+        let mut state = self.0.lock().unwrap();
+        // the last events of a finished source can still be on their way
+        if state.status != Status::Finished {
+            state.status = Status::Running;
+        }
+        state.events += 1;
+        state.last_event = Some(Utc::now());
+    }
+}
+
+/// What the sources built from one config share, like the single dgg chat messages are sent through
+pub struct SourceContext {
+    dgg_outgoing: Option<(DggSender, Outgoing)>,
+    /// the sender of the first logged in dgg chat, once one was built
+    pub dgg_sender: Option<DggSender>,
+}
+
+impl Default for SourceContext {
+    fn default() -> Self {
+        Self {
+            dgg_outgoing: Some(dgg::outgoing_channel()),
+            dgg_sender: None,
+        }
+    }
+}
+
+/// A platform in the registry
+pub struct SourceFactory {
+    pub platform: &'static str,
+    pub description: &'static str,
+    /// what `--source {platform}:{target}` takes, for example: `a channel like xqc`
+    pub target: &'static str,
+    /// from_target() maps `--source {platform}:{target}` to a config, an empty target is the default chat
+    pub from_target: fn(&str) -> Result<SourceConfig, String>,
+    /// build() returns `None` for the configs of other platforms
+    pub build: fn(&SourceConfig, &mut SourceContext) -> Option<Box<dyn ChatSource>>,
+}
+
+/// Every platform the app knows, new platforms only have to be added here
+pub struct SourceRegistry {
+    factories: Vec<SourceFactory>,
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        Self {
+            factories: vec![
+                SourceFactory {
+                    platform: "dgg",
+                    description: "destiny.gg chat over its websocket, can send messages when logged in",
+                    target: "the websocket url, destiny.gg when left out",
+                    from_target: DggSource::from_target,
+                    build: DggSource::build,
+                },
+                SourceFactory {
+                    platform: "kick",
+                    description: "kick chat of a channel over pusher",
+                    target: "a channel slug like xqc, roshtein when left out",
+                    from_target: KickSource::from_target,
+                    build: KickSource::build,
+                },
+                SourceFactory {
+                    platform: "twitch",
+                    description: "twitch chat of a channel over irc, read only",
+                    target: "a channel login name like hasanabi",
+                    from_target: TwitchSource::from_target,
+                    build: TwitchSource::build,
+                },
+                SourceFactory {
+                    platform: "rumble",
+                    description: "rumble chat of a stream over server sent events",
+                    target: "the url of a video, its embed id like v5c7u2x or its chat id",
+                    from_target: RumbleSource::from_target,
+                    build: RumbleSource::build,
+                },
+                SourceFactory {
+                    platform: "youtube",
+                    description: "youtube live chat of a channel or video, or the replay chat of a finished stream",
+                    target: "a channel handle like destiny, `video:` or `replay:` and a video id or url, destiny when left out",
+                    from_target: YoutubeSource::from_target,
+                    build: YoutubeSource::build,
+                },
+                SourceFactory {
+                    platform: "irc",
+                    description: "channels on any irc network, over tls or plain tcp",
+                    target: "a server url and the channels without `#` as its path, like ircs://irc.libera.chat/rust,libera",
+                    from_target: IrcSource::from_target,
+                    build: IrcSource::build,
+                },
+                SourceFactory {
+                    platform: "websocket",
+                    description: "any chat sending json over a websocket, its fields picked by the jsonpath expressions of the config",
+                    target: "only set in the config file",
+                    from_target: WebsocketSource::from_target,
+                    build: WebsocketSource::build,
+                },
+            ],
+        }
+    }
+}
+
+impl SourceRegistry {
+    pub fn factories(&self) -> &[SourceFactory] {
+        &self.factories
+    }
+
+    /// from_cli() maps a `--source {platform}:{target}` to a config with the factory of the platform
+    pub fn from_cli(&self, source: &str) -> Result<SourceConfig, String> {
+        let (platform, target) = source.split_once(':').unwrap_or((source, ""));
+        let factory = self
+            .factories
+            .iter()
+            .find(|factory| factory.platform == platform)
+            .ok_or(format!(
+                "unknown platform `{}`, --list-sources prints them",
+                platform
+            ))?;
+        (factory.from_target)(target.trim())
+    }
+
+    /// build() builds the source of a config with the first factory that takes it
+    pub fn build(
+        &self,
+        config: &SourceConfig,
+        context: &mut SourceContext,
+    ) -> Option<Box<dyn ChatSource>> {
+        self.factories
+            .iter()
+            .find_map(|factory| (factory.build)(config, context))
+    }
+}
+
+/// event_stream() runs a source that sends into a channel, and streams what it sends until it returns,
+/// a full channel makes the source wait instead of dropping events
+fn event_stream<F, Fut>(health: Health, run: F) -> BoxStream<'static, ChatEvent>
+where
+    F: FnOnce(Sender<ChatEvent>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(100);
+    tokio::spawn(run(tx));
+
+    Box::pin(stream::unfold(rx, move |mut rx| {
+        let health = health.clone();
+        async move {
+            let event = rx.recv().await?;
+            health.event();
+            Some((event, rx))
+        }
+    }))
+}
+
+struct DggSource {
+    config: SourceConfig,
+    outgoing: Option<Outgoing>,
+}
+
+impl DggSource {
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        let url = match target {
+            "" => config::DEFAULT_DGG_URL,
+            url => url,
+        };
+        Ok(SourceConfig::Dgg {
+            url: url.to_string(),
+            auth_token: config::default_dgg_auth_token(),
+        })
+    }
+
+    fn build(config: &SourceConfig, context: &mut SourceContext) -> Option<Box<dyn ChatSource>> {
+        let SourceConfig::Dgg { auth_token, .. } = config else {
+            return None;
+        };
+        // messages typed in the terminal or web ui are sent by the first logged in dgg chat
+        let outgoing = match auth_token {
+            Some(_) => context.dgg_outgoing.take().map(|(sender, outgoing)| {
+                context.dgg_sender = Some(sender);
+                outgoing
+            }),
+            None => None,
+        };
+        Some(Box::new(DggSource {
+            config: config.clone(),
+            outgoing,
+        }))
+    }
+}
+
+impl ChatSource for DggSource {
+    fn platform(&self) -> &'static str {
+        self.config.platform()
+    }
+
+    fn name(&self) -> String {
+        self.config.name()
+    }
+
+    fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+
+    fn run(self: Box<Self>, health: Health) -> BoxStream<'static, ChatEvent> {
+        let SourceConfig::Dgg { url, auth_token } = self.config else {
+            unreachable!("dgg source built from another config")
+        };
+        let outgoing = self.outgoing;
+        event_stream(health.clone(), move |tx| {
+            dgg::main(tx, url, auth_token, outgoing, health)
+        })
+    }
+}
+
+/// simple_source! implements a source that is nothing but its config
+macro_rules! simple_source {
+    ($source:ident, $variant:ident { $($field:ident),* } => $main:expr) => {
+        struct $source {
+            config: SourceConfig,
+        }
+
+        impl $source {
+            fn build(
+                config: &SourceConfig,
+                _context: &mut SourceContext,
+            ) -> Option<Box<dyn ChatSource>> {
+                matches!(config, SourceConfig::$variant { .. }).then(|| {
+                    Box::new($source {
+                        config: config.clone(),
+                    }) as Box<dyn ChatSource>
+                })
+            }
+        }
+
+        impl ChatSource for $source {
+            fn platform(&self) -> &'static str {
+                self.config.platform()
+            }
+
+            fn name(&self) -> String {
+                self.config.name()
+            }
+
+            fn config(&self) -> &SourceConfig {
+                &self.config
+            }
+
+            fn run(self: Box<Self>, health: Health) -> BoxStream<'static, ChatEvent> {
+                let SourceConfig::$variant { $($field),* } = self.config else {
+                    unreachable!(concat!(stringify!($source), " built from another config"))
+                };
+                event_stream(health.clone(), move |tx| $main(tx, $($field),*, health))
+            }
+        }
+    };
+}
+
+simple_source!(KickSource, Kick { channel, api_url, pusher_url } => kick::main);
+simple_source!(TwitchSource, Twitch { channel, url } => twitch::main);
+simple_source!(RumbleSource, Rumble { stream, chat_url } => rumble::main);
+simple_source!(YoutubeSource, Youtube { target, proxy } => youtube::scraper::main);
+simple_source!(IrcSource, Irc { network } => irc::main);
+simple_source!(WebsocketSource, Websocket { chat } => websocket::main);
+
+impl KickSource {
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        let channel = match target {
+            "" => config::DEFAULT_KICK_CHANNEL,
+            channel => channel,
+        };
+        Ok(SourceConfig::Kick {
+            channel: channel.to_string(),
+            api_url: config::DEFAULT_KICK_API_URL.to_string(),
+            pusher_url: config::DEFAULT_KICK_PUSHER_URL.to_string(),
+        })
+    }
+}
+
+impl TwitchSource {
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        if target.is_empty() {
+            return Err("twitch needs a channel, like twitch:hasanabi".to_string());
+        }
+        Ok(SourceConfig::Twitch {
+            channel: target.to_string(),
+            url: config::DEFAULT_TWITCH_URL.to_string(),
+        })
+    }
+}
+
+impl RumbleSource {
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        if target.is_empty() {
+            return Err("rumble needs a stream, like rumble:v5c7u2x".to_string());
+        }
+        Ok(SourceConfig::Rumble {
+            stream: target.to_string(),
+            chat_url: config::DEFAULT_RUMBLE_CHAT_URL.to_string(),
+        })
+    }
+}
+
+impl YoutubeSource {
+    /// from_target() reads `video:{video}` and `replay:{video}`, a url is a video
+    /// and anything else a channel handle, with or without the `@`
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        let target = match target.split_once(':') {
+            Some(("video", video)) => YoutubeTarget::Video(video.to_string()),
+            Some(("replay", video)) => YoutubeTarget::Replay(video.to_string()),
+            Some(("channel", channel)) => YoutubeTarget::Channel(channel.to_string()),
+            _ if target.contains('/') => YoutubeTarget::Video(target.to_string()),
+            _ if target.is_empty() => {
+                YoutubeTarget::Channel(config::DEFAULT_YOUTUBE_CHANNEL.to_string())
+            }
+            _ => YoutubeTarget::Channel(target.trim_start_matches('@').to_string()),
+        };
+        Ok(SourceConfig::Youtube {
+            target,
+            proxy: None,
+        })
+    }
+}
+
+impl IrcSource {
+    /// from_target() takes the channels from the path of the server url,
+    /// as a `#` would start the fragment of the url
+    fn from_target(target: &str) -> Result<SourceConfig, String> {
+        let mut url = url::Url::parse(target).map_err(|err| {
+            format!(
+                "irc needs a server url like irc:ircs://irc.libera.chat/rust, {}",
+                err
+            )
+        })?;
+        let channels: Vec<String> = url
+            .path()
+            .split(',')
+            .map(|channel| channel.trim_matches('/'))
+            .filter(|channel| !channel.is_empty())
+            .map(|channel| channel.to_string())
+            .collect();
+        if channels.is_empty() {
+            return Err(format!(
+                "no channels to join on {}, list them as the path like ircs://irc.libera.chat/rust,libera",
+                target
+            ));
+        }
+        url.set_path("");
+        Ok(SourceConfig::Irc {
+            network: IrcNetwork {
+                server: url.to_string(),
+                network: None,
+                nick: config::default_irc_nick(),
+                channels,
+                auth: None,
+            },
+        })
+    }
+}
+
+impl WebsocketSource {
+    fn from_target(_target: &str) -> Result<SourceConfig, String> {
+        Err("websocket chats need jsonpath expressions, add them to the config file".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_cli(source: &str) -> Result<SourceConfig, String> {
+        SourceRegistry::default().from_cli(source)
+    }
+
+    #[test]
+    fn defaults() {
+        assert!(matches!(
+            from_cli("dgg").unwrap(),
+            SourceConfig::Dgg { url, .. } if url == config::DEFAULT_DGG_URL
+        ));
+        assert!(matches!(
+            from_cli("kick:").unwrap(),
+            SourceConfig::Kick { channel, .. } if channel == config::DEFAULT_KICK_CHANNEL
+        ));
+        assert!(matches!(
+            from_cli("youtube").unwrap(),
+            SourceConfig::Youtube { target: YoutubeTarget::Channel(channel), .. }
+                if channel == config::DEFAULT_YOUTUBE_CHANNEL
+        ));
+        assert!(from_cli("twitch").is_err());
+        assert!(from_cli("rumble").is_err());
+    }
+
+    #[test]
+    fn targets() {
+        assert!(matches!(
+            from_cli("dgg:ws://localhost:9000/ws").unwrap(),
+            SourceConfig::Dgg { url, .. } if url == "ws://localhost:9000/ws"
+        ));
+        assert!(matches!(
+            from_cli("kick:xqc").unwrap(),
+            SourceConfig::Kick { channel, api_url, .. }
+                if channel == "xqc" && api_url == config::DEFAULT_KICK_API_URL
+        ));
+        assert!(matches!(
+            from_cli("twitch:hasanabi").unwrap(),
+            SourceConfig::Twitch { channel, .. } if channel == "hasanabi"
+        ));
+        assert!(matches!(
+            from_cli("rumble:v5c7u2x").unwrap(),
+            SourceConfig::Rumble { stream, .. } if stream == "v5c7u2x"
+        ));
+    }
+
+    #[test]
+    fn youtube_targets() {
+        let target = |source: &str| match from_cli(source).unwrap() {
+            SourceConfig::Youtube { target, .. } => target.to_string(),
+            other => panic!("expected youtube, got {:?}", other),
+        };
+        assert_eq!(target("youtube:@destiny"), "destiny");
+        assert_eq!(target("youtube:channel:destiny"), "destiny");
+        assert_eq!(target("youtube:video:dQw4w9WgXcQ"), "dQw4w9WgXcQ");
+        assert_eq!(target("youtube:replay:dQw4w9WgXcQ"), "dQw4w9WgXcQ replay");
+        assert!(matches!(
+            from_cli("youtube:https://youtu.be/dQw4w9WgXcQ").unwrap(),
+            SourceConfig::Youtube { target: YoutubeTarget::Video(video), .. }
+                if video == "https://youtu.be/dQw4w9WgXcQ"
+        ));
+    }
+
+    #[test]
+    fn irc_targets() {
+        let SourceConfig::Irc { network } =
+            from_cli("irc:ircs://irc.libera.chat/rust,libera").unwrap()
+        else {
+            panic!("expected irc");
+        };
+        assert_eq!(network.server, "ircs://irc.libera.chat");
+        assert_eq!(network.channels, ["rust", "libera"]);
+
+        let SourceConfig::Irc { network } = from_cli("irc:irc://localhost:6667/test").unwrap()
+        else {
+            panic!("expected irc");
+        };
+        assert_eq!(network.server, "irc://localhost:6667");
+        assert_eq!(network.channels, ["test"]);
+
+        assert!(from_cli("irc:ircs://irc.libera.chat").is_err());
+        assert!(from_cli("irc:libera").is_err());
+    }
+
+    #[test]
+    fn unknown_platforms() {
+        assert!(from_cli("mixer:ninja").is_err());
+        assert!(from_cli("websocket:wss://chat.example.com").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::source::Health;
+use log::{info, warn};
+use rand::Rng;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use tokio_tungstenite::tungstenite;
+
+/// a connection that stayed up at least this long is considered healthy,
+/// the next outage starts backing off from the initial delay again
+const HEALTHY_CONNECTION: Duration = Duration::from_secs(60);
+
+/// Exponential backoff with "equal jitter",
+/// every delay is somewhere between half and all of the current exponential step
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60), 2)
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, factor: u32) -> Self {
+        Self {
+            initial,
+            max,
+            factor,
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self
+            .factor
+            .checked_pow(self.attempt)
+            .and_then(|multiplier| self.initial.checked_mul(multiplier))
+            .unwrap_or(self.max)
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=half);
+        half + jitter
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Why a chat source stopped
+#[derive(Debug)]
+pub enum SourceError {
+    WebSocket(Box<tungstenite::Error>),
+    Http(reqwest::Error),
+    /// the server closed the connection, or nothing reads the chat events anymore
+    Closed,
+    Other(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::WebSocket(err) => write!(f, "websocket error, {}", err),
+            SourceError::Http(err) => write!(f, "http error, {}", err),
+            SourceError::Closed => write!(f, "connection closed by server"),
+            SourceError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<tungstenite::Error> for SourceError {
+    fn from(err: tungstenite::Error) -> Self {
+        SourceError::WebSocket(Box::new(err))
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for SourceError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        SourceError::Closed
+    }
+}
+
+impl From<reqwest::Error> for SourceError {
+    fn from(err: reqwest::Error) -> Self {
+        SourceError::Http(err)
+    }
+}
+
+/// Runs `connect` until it returns `Ok(())`, which means the source is done for good,
+/// every time it returns an error the outage is logged and it is started again after the next backoff delay.
+/// `health` follows along so the app can tell how the source is doing.
+pub async fn supervise<F, Fut>(name: &str, health: &Health, mut backoff: Backoff, mut connect: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), SourceError>>,
+{
+    loop {
+        health.connecting();
+        let started = Instant::now();
+        let result = connect().await;
+        let uptime = started.elapsed();
+
+        let Err(err) = result else {
+            info!("{} finished after {:?}", name, uptime);
+            health.finished();
+            return;
+        };
+
+        if uptime >= HEALTHY_CONNECTION {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        health.reconnecting(&err);
+        warn!(
+            "{} disconnected after {:?}, {}, reconnecting in {:?}",
+            name, uptime, err, delay
+        );
+        sleep(delay).await;
+    }
+}
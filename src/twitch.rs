@@ -0,0 +1,533 @@
+use crate::irc::IrcMessage;
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{
+    Author, Badge, ChatEvent, ChatLocation, ChatMsg, ChatNotice, Fragment, NoticeKind, Role,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use rand::Rng;
+use std::str::FromStr;
+use tokio::sync::mpsc::Sender;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// emote images are served from `{EMOTE_CDN_URL}/{emote id}/default/dark/1.0`
+const EMOTE_CDN_URL: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+
+pub async fn main(tx: Sender<ChatEvent>, channel: String, url: String, health: Health) {
+    let name = format!("twitch {}", channel);
+    supervise(&name, &health, Backoff::default(), || {
+        connect(tx.clone(), channel.clone(), url.clone())
+    })
+    .await
+}
+
+async fn connect(tx: Sender<ChatEvent>, channel: String, url: String) -> Result<(), SourceError> {
+    let channel = channel.trim_start_matches('#').to_lowercase();
+
+    debug!("Connecting to twitch irc {}", url);
+    let (mut socket, _) = connect_async(url.as_str()).await?;
+
+    // `justinfan` followed by any number logs in anonymously, read only
+    let nick = format!("justinfan{}", rand::thread_rng().gen_range(10000..100000));
+    socket
+        .send(Message::Text(
+            "CAP REQ :twitch.tv/tags twitch.tv/commands".to_string(),
+        ))
+        .await?;
+    socket.send(Message::Text(format!("NICK {}", nick))).await?;
+    socket
+        .send(Message::Text(format!("JOIN #{}", channel)))
+        .await?;
+    info!("joined twitch chat #{} as {}", channel, nick);
+
+    loop {
+        let msg = socket.next().await.ok_or(SourceError::Closed)??;
+        if !msg.is_text() {
+            continue;
+        }
+        let raw_msg_text = msg.to_text()?;
+
+        // a frame can hold several irc lines
+        for line in raw_msg_text.lines().filter(|line| !line.is_empty()) {
+            debug!("{}", line);
+            let irc_msg = match line.parse::<IrcMessage>() {
+                Ok(irc_msg) => irc_msg,
+                Err(err) => {
+                    warn!("Can't parse twitch irc line `{}`, {}", line, err);
+                    continue;
+                }
+            };
+
+            match irc_msg.command.as_str() {
+                "PING" => {
+                    let pong = format!("PONG :{}", irc_msg.trailing().unwrap_or_default());
+                    socket.send(Message::Text(pong)).await?;
+                }
+                // twitch is about to restart the server
+                "RECONNECT" => {
+                    return Err(SourceError::Other("twitch asked to reconnect".to_string()))
+                }
+                _ => {
+                    if let Some(chat_event) = irc_msg.into_chat_event(&channel, line) {
+                        tx.send(chat_event).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The twitch specific tags of a line, like `tmi-sent-ts` and `badges`
+impl IrcMessage {
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.tag("tmi-sent-ts")
+            .and_then(|ts| ts.parse().ok())
+            .and_then(NaiveDateTime::from_timestamp_millis)
+            .map(|timestamp| DateTime::from_naive_utc_and_offset(timestamp, Utc))
+            .unwrap_or_else(Utc::now)
+    }
+
+    fn tag_number<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.tag(key).and_then(|value| value.parse().ok())
+    }
+
+    /// author() reads the roles from the `badges` tag, like `moderator/1,subscriber/12`
+    fn author(&self) -> Author {
+        let login = self.tag("login").or(self.nick()).unwrap_or_default();
+        let mut author = Author {
+            id: self.tag("user-id").map(|id| id.to_string()),
+            name: self.tag("display-name").unwrap_or(login).to_string(),
+            ..Author::default()
+        };
+        for badge in self.tag("badges").unwrap_or_default().split(',') {
+            let Some((name, _version)) = badge.split_once('/') else {
+                continue;
+            };
+            if let Some(role) = Role::from_name(name) {
+                author.add_role(role);
+            }
+            author.badges.push(Badge {
+                name: name.to_string(),
+                image_url: None,
+            });
+        }
+        author
+    }
+
+    fn into_chat_event(self, channel: &str, raw_msg_text: &str) -> Option<ChatEvent> {
+        let timestamp = self.timestamp();
+        let kind = match self.command.as_str() {
+            "PRIVMSG" => {
+                let text = self.text()?;
+                let author = self.author();
+
+                if let Some(bits) = self.tag_number::<u64>("bits") {
+                    NoticeKind::Paid {
+                        msg_id: self.tag("id").map(|id| id.to_string()),
+                        author: author.name,
+                        amount_text: format!("{} bits", bits),
+                        amount: bits as f64,
+                        currency: "bits".to_string(),
+                        msg_text: Some(text.to_string()),
+                        sticker: None,
+                        header_color: None,
+                        body_color: None,
+                    }
+                } else {
+                    let fragments = parse_fragments(text, self.tag("emotes").unwrap_or_default());
+                    return Some(
+                        ChatMsg {
+                            id: self.tag("id").map(|id| id.to_string()),
+                            location: ChatLocation::Twitch,
+                            channel: channel.to_string(),
+                            timestamp,
+                            stream_offset: None,
+                            msg_text: text.to_string(),
+                            fragments,
+                            author,
+                            raw_full_msg: raw_msg_text.to_string(),
+                        }
+                        .into(),
+                    );
+                }
+            }
+            "USERNOTICE" => self.user_notice()?,
+            // a user was banned or timed out, or the whole chat was cleared without a user
+            "CLEARCHAT" => {
+                let Some(username) = self.params.get(1) else {
+                    return Some(
+                        ChatNotice {
+                            location: ChatLocation::Twitch,
+                            channel: channel.to_string(),
+                            timestamp,
+                            stream_offset: None,
+                            kind: NoticeKind::Broadcast {
+                                msg_text: "chat was cleared by a moderator".to_string(),
+                            },
+                            raw_full_msg: raw_msg_text.to_string(),
+                        }
+                        .into(),
+                    );
+                };
+                match self.tag_number::<i64>("ban-duration") {
+                    Some(secs) => NoticeKind::UserMuted {
+                        username: username.clone(),
+                        muted_by: None,
+                        expires_at: Some(timestamp + Duration::seconds(secs)),
+                    },
+                    None => NoticeKind::UserBanned {
+                        username: username.clone(),
+                        banned_by: None,
+                        permanent: true,
+                        expires_at: None,
+                    },
+                }
+            }
+            "CLEARMSG" => NoticeKind::MsgDeleted {
+                msg_id: self.tag("target-msg-id")?.to_string(),
+            },
+            _ => return None,
+        };
+
+        Some(
+            ChatNotice {
+                location: ChatLocation::Twitch,
+                channel: channel.to_string(),
+                timestamp,
+                stream_offset: None,
+                kind,
+                raw_full_msg: raw_msg_text.to_string(),
+            }
+            .into(),
+        )
+    }
+
+    /// user_notice() reads the subs, gifts and raids of a USERNOTICE by its `msg-id`,
+    /// every other kind is shown with the text twitch gives it
+    fn user_notice(&self) -> Option<NoticeKind> {
+        let username = self.author().name;
+        let msg_text = self.trailing().map(|text| text.to_string());
+        let kind = match self.tag("msg-id")? {
+            "sub" | "resub" => NoticeKind::Subscription {
+                username,
+                months: self.tag_number("msg-param-cumulative-months"),
+                tier: self.tag("msg-param-sub-plan").map(sub_plan_name),
+                msg_text,
+            },
+            "subgift" | "anonsubgift" => NoticeKind::GiftedSubs {
+                gifter: username,
+                count: 1,
+                recipients: self
+                    .tag("msg-param-recipient-display-name")
+                    .map(|recipient| vec![recipient.to_string()])
+                    .unwrap_or_default(),
+            },
+            "submysterygift" | "anonsubmysterygift" => NoticeKind::GiftedSubs {
+                gifter: username,
+                count: self.tag_number("msg-param-mass-gift-count").unwrap_or(1),
+                recipients: vec![],
+            },
+            "raid" => NoticeKind::Host {
+                host: self
+                    .tag("msg-param-displayName")
+                    .map(|host| host.to_string())
+                    .unwrap_or(username),
+                viewers: self.tag_number("msg-param-viewerCount").unwrap_or_default(),
+                msg_text,
+            },
+            _ => NoticeKind::Broadcast {
+                msg_text: self
+                    .tag("system-msg")
+                    .map(|text| text.to_string())
+                    .or(msg_text)?,
+            },
+        };
+        Some(kind)
+    }
+}
+
+/// sub_plan_name() names the `msg-param-sub-plan` of a sub
+fn sub_plan_name(plan: &str) -> String {
+    match plan {
+        "Prime" => "Prime".to_string(),
+        "1000" => "Tier 1".to_string(),
+        "2000" => "Tier 2".to_string(),
+        "3000" => "Tier 3".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// parse_fragments() splits a message at the emotes of the `emotes` tag,
+/// which lists the char ranges of every emote like `25:0-4,12-16/1902:6-10`
+fn parse_fragments(text: &str, emotes: &str) -> Vec<Fragment> {
+    let mut ranges: Vec<(usize, usize, &str)> = vec![];
+    for emote in emotes.split('/') {
+        let Some((id, positions)) = emote.split_once(':') else {
+            continue;
+        };
+        for position in positions.split(',') {
+            let Some((start, end)) = position.split_once('-') else {
+                continue;
+            };
+            if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                ranges.push((start, end, id));
+            }
+        }
+    }
+    ranges.sort();
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut fragments = vec![];
+    let mut next = 0;
+    for (start, end, id) in ranges {
+        if start < next || end < start || end >= chars.len() {
+            continue;
+        }
+        if start > next {
+            let text: String = chars[next..start].iter().collect();
+            fragments.extend(Fragment::parse_text(&text));
+        }
+        fragments.push(Fragment::Emote {
+            id: id.to_string(),
+            name: chars[start..=end].iter().collect(),
+            url: format!("{}/{}/default/dark/1.0", EMOTE_CDN_URL, id),
+        });
+        next = end + 1;
+    }
+    if next < chars.len() {
+        let text: String = chars[next..].iter().collect();
+        fragments.extend(Fragment::parse_text(&text));
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    const PRIVMSG: &str = r"@badge-info=subscriber/14;badges=moderator/1,subscriber/12;color=#1E90FF;display-name=Bob;emotes=25:0-4,12-16/1902:6-10;id=885196de-cb67-427a-baa8-82f9b0fcd05f;mod=1;tmi-sent-ts=1700647200000;user-id=1337 :bob!bob@bob.tmi.twitch.tv PRIVMSG #channel :Kappa Keepo Kappa";
+
+    fn event(line: &str) -> Option<ChatEvent> {
+        line.parse::<IrcMessage>()
+            .unwrap()
+            .into_chat_event("channel", line)
+    }
+
+    fn notice_kind(line: &str) -> NoticeKind {
+        match event(line) {
+            Some(ChatEvent::Notice(notice)) => notice.kind,
+            other => panic!("expected a notice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn privmsg() {
+        let Some(ChatEvent::Msg(msg)) = event(PRIVMSG) else {
+            panic!("expected a message");
+        };
+        assert_eq!(
+            msg.id.as_deref(),
+            Some("885196de-cb67-427a-baa8-82f9b0fcd05f")
+        );
+        assert_eq!(msg.timestamp.timestamp_millis(), 1700647200000);
+        assert_eq!(msg.author.id.as_deref(), Some("1337"));
+        assert_eq!(msg.author.name, "Bob");
+        assert!(msg.author.roles.contains(&Role::Moderator));
+        assert!(msg.author.roles.contains(&Role::Subscriber));
+        assert_eq!(msg.author.badges.len(), 2);
+        assert_eq!(msg.msg_text, "Kappa Keepo Kappa");
+        assert_eq!(msg.fragments.len(), 5);
+    }
+
+    #[test]
+    fn privmsg_without_tags() {
+        let Some(ChatEvent::Msg(msg)) = event(":bob!bob@bob.tmi.twitch.tv PRIVMSG #channel :hi")
+        else {
+            panic!("expected a message");
+        };
+        assert_eq!(msg.author.name, "bob");
+        assert_eq!(msg.fragments, vec![Fragment::Text("hi".to_string())]);
+    }
+
+    #[test]
+    fn bits() {
+        let kind = notice_kind(
+            "@bits=100;display-name=Bob;id=abc-123 :bob!bob@bob.tmi.twitch.tv PRIVMSG #channel :cheer100 gg",
+        );
+        // the id is kept so a CLEARMSG can delete the cheer like any message
+        assert!(matches!(
+            kind,
+            NoticeKind::Paid { msg_id: Some(msg_id), author, amount, currency, .. }
+                if msg_id == "abc-123" && author == "Bob" && amount == 100.0 && currency == "bits"
+        ));
+    }
+
+    #[test]
+    fn clearchat() {
+        assert!(matches!(
+            notice_kind("@ban-duration=600;tmi-sent-ts=1700647200000 :tmi.twitch.tv CLEARCHAT #channel :bob"),
+            NoticeKind::UserMuted { username, expires_at: Some(expires_at), .. }
+                if username == "bob" && expires_at.timestamp() == 1700647800
+        ));
+        assert!(matches!(
+            notice_kind(":tmi.twitch.tv CLEARCHAT #channel :bob"),
+            NoticeKind::UserBanned { username, permanent: true, expires_at: None, .. } if username == "bob"
+        ));
+        assert!(matches!(
+            notice_kind(":tmi.twitch.tv CLEARCHAT #channel"),
+            NoticeKind::Broadcast { .. }
+        ));
+    }
+
+    #[test]
+    fn clearmsg() {
+        assert!(matches!(
+            notice_kind("@login=bob;target-msg-id=abc-123 :tmi.twitch.tv CLEARMSG #channel :bad"),
+            NoticeKind::MsgDeleted { msg_id } if msg_id == "abc-123"
+        ));
+        assert!(event(":tmi.twitch.tv CLEARMSG #channel :bad").is_none());
+    }
+
+    #[test]
+    fn user_notices() {
+        assert!(matches!(
+            notice_kind(r"@display-name=Bob;msg-id=resub;msg-param-cumulative-months=14;msg-param-sub-plan=2000;system-msg=Bob\ssubscribed :tmi.twitch.tv USERNOTICE #channel :still here"),
+            NoticeKind::Subscription { username, months: Some(14), tier: Some(tier), msg_text: Some(msg_text) }
+                if username == "Bob" && tier == "Tier 2" && msg_text == "still here"
+        ));
+        assert!(matches!(
+            notice_kind("@display-name=Bob;msg-id=subgift;msg-param-recipient-display-name=Alice :tmi.twitch.tv USERNOTICE #channel"),
+            NoticeKind::GiftedSubs { gifter, count: 1, recipients } if gifter == "Bob" && recipients == ["Alice"]
+        ));
+        assert!(matches!(
+            notice_kind("@display-name=Bob;msg-id=submysterygift;msg-param-mass-gift-count=5 :tmi.twitch.tv USERNOTICE #channel"),
+            NoticeKind::GiftedSubs { count: 5, recipients, .. } if recipients.is_empty()
+        ));
+        assert!(matches!(
+            notice_kind("@display-name=Raider;msg-id=raid;msg-param-displayName=Raider;msg-param-viewerCount=42 :tmi.twitch.tv USERNOTICE #channel"),
+            NoticeKind::Host { host, viewers: 42, .. } if host == "Raider"
+        ));
+        assert!(matches!(
+            notice_kind(r"@msg-id=announcement;system-msg=Bob\sannounced :tmi.twitch.tv USERNOTICE #channel"),
+            NoticeKind::Broadcast { msg_text } if msg_text == "Bob announced"
+        ));
+    }
+
+    #[test]
+    fn other_commands() {
+        assert!(event(":tmi.twitch.tv ROOMSTATE #channel").is_none());
+        assert!(event(":bob!bob@bob.tmi.twitch.tv JOIN #channel").is_none());
+    }
+
+    #[test]
+    fn emote_fragments() {
+        let fragments = parse_fragments("Kappa Keepo Kappa", "25:0-4,12-16/1902:6-10");
+        let names: Vec<_> = fragments.iter().map(Fragment::plain_text).collect();
+        assert_eq!(names, [":Kappa:", " ", ":Keepo:", " ", ":Kappa:"]);
+        assert!(matches!(
+            &fragments[2],
+            Fragment::Emote { id, url, .. }
+                if id == "1902" && url == "https://static-cdn.jtvnw.net/emoticons/v2/1902/default/dark/1.0"
+        ));
+    }
+
+    #[test]
+    fn emote_fragments_count_chars() {
+        // positions are in chars, not bytes
+        let fragments = parse_fragments("héllo Kappa", "25:6-10");
+        assert_eq!(fragments[0], Fragment::Text("héllo ".to_string()));
+        assert!(matches!(&fragments[1], Fragment::Emote { name, .. } if name == "Kappa"));
+    }
+
+    #[test]
+    fn emote_fragments_out_of_range() {
+        let fragments = parse_fragments("hi", "25:0-4,x-y/broken");
+        assert_eq!(fragments, vec![Fragment::Text("hi".to_string())]);
+    }
+
+    #[test]
+    fn sub_plans() {
+        assert_eq!(sub_plan_name("Prime"), "Prime");
+        assert_eq!(sub_plan_name("1000"), "Tier 1");
+        assert_eq!(sub_plan_name("3000"), "Tier 3");
+        assert_eq!(sub_plan_name("9000"), "9000");
+    }
+
+    #[tokio::test]
+    async fn twitch_stand_in() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, mut rx) = mpsc::channel(16);
+        let chat = tokio::spawn(connect(tx, "#Channel".to_string(), url));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let mut received = vec![];
+        for _ in 0..3 {
+            let frame = socket.next().await.unwrap().unwrap();
+            received.push(frame.into_text().unwrap());
+        }
+        assert_eq!(received[0], "CAP REQ :twitch.tv/tags twitch.tv/commands");
+        assert!(received[1].starts_with("NICK justinfan"));
+        assert_eq!(received[2], "JOIN #channel");
+
+        // twitch sends several lines in one frame
+        socket
+            .send(Message::Text(format!(
+                "PING :tmi.twitch.tv\r\n{}\r\n:tmi.twitch.tv CLEARCHAT #channel :bob\r\n",
+                PRIVMSG
+            )))
+            .await
+            .unwrap();
+        let pong = socket.next().await.unwrap().unwrap();
+        assert_eq!(pong.to_text().unwrap(), "PONG :tmi.twitch.tv");
+
+        let Some(ChatEvent::Msg(msg)) = rx.recv().await else {
+            panic!("expected a message");
+        };
+        assert_eq!(msg.channel, "channel");
+        assert_eq!(msg.raw_full_msg, PRIVMSG);
+        assert!(matches!(
+            rx.recv().await,
+            Some(ChatEvent::Notice(ChatNotice {
+                kind: NoticeKind::UserBanned { .. },
+                ..
+            }))
+        ));
+
+        socket
+            .send(Message::Text(":tmi.twitch.tv RECONNECT".to_string()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            chat.await.unwrap(),
+            Err(SourceError::Other(err)) if err == "twitch asked to reconnect"
+        ));
+    }
+
+    #[tokio::test]
+    async fn stops_without_reader() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel(16);
+        let chat = tokio::spawn(connect(tx, "#channel".to_string(), url));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        for _ in 0..3 {
+            socket.next().await.unwrap().unwrap();
+        }
+
+        // nothing reads the chat events anymore, the message can't be passed on
+        drop(rx);
+        socket
+            .send(Message::Text(PRIVMSG.to_string()))
+            .await
+            .unwrap();
+        assert!(matches!(chat.await.unwrap(), Err(SourceError::Closed)));
+    }
+}
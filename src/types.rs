@@ -3,18 +3,51 @@ use askama::Template;
 use chrono::{DateTime, Local, Utc};
 use colored::{ColoredString, Colorize};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Deserialize, Serialize, Clone, Template)]
+#[derive(Debug, Serialize, Clone, Template)]
 #[template(
     ext = "html",
-    source = r#"<div class="{{class}}"><span>{{fmt_time}} {{location}} {{author}}</span><span>  {{msg}}</span></div>"#
+    source = r#"<div class="{{class}}"{% if let Some(id) = id %} data-source="{{location}}" data-msg-id="{{id}}"{% endif %}><span>{{fmt_time}} {{location}} {% for badge in badges %}{% if let Some(url) = badge.image_url %}<img class="badge" src="{{url}}" alt="{{badge.name}}" title="{{badge.name}}">{% endif %}{% endfor %}{{author}}</span><span>  {% for fragment in fragments %}{% match fragment %}{% when Fragment::Text with (text) %}{{text}}{% when Fragment::Emote with { id: _, name, url } %}<img class="emote" src="{{url}}" alt="{{name}}" title="{{name}}">{% when Fragment::Mention with (name) %}<span class="mention">@{{name}}</span>{% when Fragment::Link with { url, text } %}<a href="{{url}}" target="_blank" rel="noopener noreferrer">{{text}}</a>{% when Fragment::Emoji with { emoji, name } %}<span class="emoji"{% if let Some(name) = name %} title="{{name}}"{% endif %}>{{emoji}}</span>{% endmatch %}{% endfor %}</span></div>"#
 )]
 struct MsgTemplate<'a> {
-    msg: &'a str,
+    id: Option<&'a str>,
+    fragments: &'a [Fragment],
     location: &'a str,
     fmt_time: &'a str,
     author: &'a str,
+    badges: &'a [Badge],
+    class: &'a str,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Template)]
+#[template(
+    ext = "html",
+    source = r#"<div class="notice {{class}} {{kind}}"{% if let Some(id) = msg_id %} data-source="{{location}}" data-msg-id="{{id}}"{% endif %}{% if let Some(id) = target_id %} data-source="{{location}}" data-target-id="{{id}}"{% endif %}{% if let Some(color) = background_color %} style="background-color: {{color}}"{% endif %}><span>{{fmt_time}} {{location}}</span><span>  {{text}}</span></div>"#
+)]
+struct NoticeTemplate<'a> {
+    msg_id: Option<&'a str>,
+    target_id: Option<&'a str>,
+    background_color: Option<String>,
+    text: &'a str,
+    location: &'a str,
+    fmt_time: &'a str,
+    class: &'a str,
+    kind: &'a str,
+}
+
+/// A message that takes the place of an earlier one, the web ui swaps the two
+#[derive(Debug, Serialize, Clone, Template)]
+#[template(
+    ext = "html",
+    source = r#"<div class="notice {{class}} replaced" data-source="{{source}}" data-target-id="{{target_id}}">{{msg_html|safe}}</div>"#
+)]
+struct ReplacedTemplate<'a> {
+    /// the source name of the replaced message, ids are only unique within a source
+    source: &'a str,
+    target_id: &'a str,
     class: &'a str,
+    msg_html: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -22,6 +55,10 @@ pub enum ChatLocation {
     Dgg,
     YouTube,
     Kick,
+    Twitch,
+    Rumble,
+    Irc,
+    Websocket,
 }
 impl ChatLocation {
     pub fn name(&self) -> &'static str {
@@ -29,82 +66,845 @@ impl ChatLocation {
             ChatLocation::Dgg => "dgg",
             ChatLocation::YouTube => "youtube",
             ChatLocation::Kick => "kick",
+            ChatLocation::Twitch => "twitch",
+            ChatLocation::Rumble => "rumble",
+            ChatLocation::Irc => "irc",
+            ChatLocation::Websocket => "websocket",
+        }
+    }
+}
+
+/// Everything a chat source can send through the broadcast channel
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum ChatEvent {
+    Msg(ChatMsg),
+    Notice(ChatNotice),
+}
+
+impl ChatEvent {
+    pub fn to_html(&self) -> String {
+        match self {
+            ChatEvent::Msg(msg) => msg.to_html(),
+            ChatEvent::Notice(notice) => notice.to_html(),
+        }
+    }
+
+    pub fn cli_format(&self) -> String {
+        match self {
+            ChatEvent::Msg(msg) => msg.cli_format(),
+            ChatEvent::Notice(notice) => notice.cli_format(),
+        }
+    }
+}
+
+impl From<ChatMsg> for ChatEvent {
+    fn from(msg: ChatMsg) -> Self {
+        ChatEvent::Msg(msg)
+    }
+}
+
+impl From<ChatNotice> for ChatEvent {
+    fn from(notice: ChatNotice) -> Self {
+        ChatEvent::Notice(notice)
+    }
+}
+
+/// The author of a message, filled in by every chat location as far as it tells
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Author {
+    /// the account id on the chat location, dgg only has nicks
+    pub id: Option<String>,
+    /// the display name
+    pub name: String,
+    pub roles: Vec<Role>,
+    /// the badges shown next to the name, like subscriber or member badges
+    pub badges: Vec<Badge>,
+    pub avatar_url: Option<String>,
+}
+
+impl Author {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// add_role() adds a role once, several badges can map to the same role
+    pub fn add_role(&mut self, role: Role) {
+        if !self.has_role(role) {
+            self.roles.push(role);
+        }
+    }
+}
+
+/// What an author is allowed or known to be in a chat
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// the owner of the channel
+    Broadcaster,
+    Moderator,
+    /// staff of the chat location itself
+    Admin,
+    Vip,
+    /// a subscriber or youtube member
+    Subscriber,
+    Verified,
+    Bot,
+}
+
+impl Role {
+    /// from_name() reads the name a chat location gives a role, like `moderator` or `OWNER`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let role = match name.to_lowercase().as_str() {
+            "broadcaster" | "owner" => Role::Broadcaster,
+            "moderator" => Role::Moderator,
+            "admin" | "staff" => Role::Admin,
+            "vip" => Role::Vip,
+            "subscriber" | "member" => Role::Subscriber,
+            "verified" => Role::Verified,
+            "bot" => Role::Bot,
+            _ => return None,
+        };
+        Some(role)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Badge {
+    /// for example: `Moderator` or `Member (6 months)`
+    pub name: String,
+    pub image_url: Option<String>,
+}
+
+/// A piece of a message, rendered differently in the terminal and the web ui
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum Fragment {
+    Text(String),
+    /// a platform emote, shown as `:name:` in the terminal and as an image in the web ui
+    Emote {
+        id: String,
+        name: String,
+        url: String,
+    },
+    /// a user named in the message like `@bob`, without the `@`
+    Mention(String),
+    /// an http or https link, `text` is what the message shows for it
+    Link {
+        url: String,
+        text: String,
+    },
+    /// a unicode emoji, `name` is its shortcut like `:smile:` when the platform gives one
+    Emoji {
+        emoji: String,
+        name: Option<String>,
+    },
+}
+
+impl Fragment {
+    pub fn plain_text(&self) -> String {
+        match self {
+            Fragment::Text(text) => text.clone(),
+            Fragment::Emote { name, .. } => format!(":{}:", name),
+            Fragment::Mention(name) => format!("@{}", name),
+            Fragment::Link { text, .. } => text.clone(),
+            Fragment::Emoji { emoji, .. } => emoji.clone(),
         }
     }
+
+    /// cli_format() is the plain text of the fragment, with emotes, mentions and links highlighted
+    pub fn cli_format(&self) -> String {
+        let plain_text = self.plain_text();
+        match self {
+            Fragment::Text(_) | Fragment::Emoji { .. } => plain_text,
+            Fragment::Emote { .. } => plain_text.yellow().to_string(),
+            Fragment::Mention(_) => plain_text.bold().to_string(),
+            Fragment::Link { .. } => plain_text.blue().underline().to_string(),
+        }
+    }
+
+    /// parse_text() splits plain text at the `@mentions` and http links in it,
+    /// the punctuation right after a mention or link is kept as text
+    pub fn parse_text(text: &str) -> Vec<Fragment> {
+        let mut fragments = vec![];
+        let mut plain = String::new();
+
+        for word in text.split_inclusive(char::is_whitespace) {
+            let token = word.trim_end();
+            let (fragment, len) = if token.starts_with("http://") || token.starts_with("https://") {
+                let link = trim_link(token);
+                let fragment = link
+                    .split_once("://")
+                    .filter(|(_, rest)| !rest.is_empty())
+                    .map(|_| Fragment::Link {
+                        url: link.to_string(),
+                        text: link.to_string(),
+                    });
+                (fragment, link.len())
+            } else if let Some(name) = token.strip_prefix('@') {
+                let name = &name[..name.find(|c| !is_word_char(c)).unwrap_or(name.len())];
+                let fragment = (!name.is_empty()).then(|| Fragment::Mention(name.to_string()));
+                (fragment, name.len() + 1)
+            } else {
+                (None, 0)
+            };
+
+            match fragment {
+                Some(fragment) => {
+                    if !plain.is_empty() {
+                        fragments.push(Fragment::Text(std::mem::take(&mut plain)));
+                    }
+                    fragments.push(fragment);
+                    plain.push_str(&word[len..]);
+                }
+                None => plain.push_str(word),
+            }
+        }
+        if !plain.is_empty() {
+            fragments.push(Fragment::Text(plain));
+        }
+        fragments
+    }
+}
+
+/// the chars of a user name in a mention
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// trim_link() drops the punctuation that ends a sentence rather than the link before it,
+/// closing brackets are only dropped when the link doesn't open them, like in `(see https://example.com)`
+fn trim_link(mut link: &str) -> &str {
+    while let Some(c) = link.chars().last() {
+        let trailing = match c {
+            '.' | ',' | '!' | '?' | ':' | ';' | '"' | '\'' => true,
+            ')' => link.matches('(').count() < link.matches(')').count(),
+            ']' => link.matches('[').count() < link.matches(']').count(),
+            _ => false,
+        };
+        if !trailing {
+            break;
+        }
+        link = &link[..link.len() - c.len_utf8()];
+    }
+    link
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ChatMsg {
+    /// the id the chat location gave the message, used to match later deletions
+    pub id: Option<String>,
     /// the chat location where the message originated from
     pub location: ChatLocation,
+    /// the channel or room within the chat location
+    /// for example: `xqc` on kick or `chat.destiny.gg` on dgg
+    pub channel: String,
     /// when the message was sent
     pub timestamp: DateTime<Utc>,
+    /// how far into the stream the message was sent, only known for replayed chats
+    pub stream_offset: Option<Duration>,
     /// the text of the message
     pub msg_text: String,
-    /// the display name of the author
-    pub author: String,
+    /// the message split into text and emotes
+    pub fragments: Vec<Fragment>,
+    pub author: Author,
     /// raw full message as it was received
     pub raw_full_msg: String,
 }
 
 impl ChatMsg {
-    pub fn from_dgg_msg(dgg_msg: DggChatMsg, raw_msg_text: String) -> Self {
+    pub fn from_dgg_msg(dgg_msg: DggChatMsg, channel: String, raw_msg_text: String) -> Self {
+        let author = dgg_msg.author();
         ChatMsg {
+            id: Some(dgg_msg.id.to_string()),
             location: ChatLocation::Dgg,
+            channel,
             raw_full_msg: raw_msg_text,
             timestamp: dgg_msg.timestamp,
+            stream_offset: None,
+            fragments: Fragment::parse_text(&dgg_msg.data),
             msg_text: dgg_msg.data,
-            author: dgg_msg.nick,
+            author,
         }
     }
 
-    pub fn to_html(&self) -> String {
-        let class = match self.location {
-            ChatLocation::Dgg => "dgg",
-            ChatLocation::YouTube => "youtube",
-            ChatLocation::Kick => "kick",
-        };
-        let msg = self.msg_text.as_str();
-        let location = class;
-        let local_time: DateTime<Local> = DateTime::from(self.timestamp);
+    /// the location and channel of the message, for example: `kick/xqc`
+    pub fn source_name(&self) -> String {
+        format!("{}/{}", self.location.name(), self.channel)
+    }
 
-        let fmt_time = local_time.format("%H:%M").to_string();
-        let author = self.author.as_str();
+    pub fn to_html(&self) -> String {
+        let class = self.location.name();
+        let source_name = self.source_name();
+        let location = source_name.as_str();
+        let fmt_time = self.fmt_time();
+        let author = self.author.name.as_str();
 
         MsgTemplate {
+            id: self.id.as_deref(),
             author,
             class,
             fmt_time: fmt_time.as_str(),
+            badges: &self.author.badges,
             location,
-            msg,
+            fragments: &self.fragments,
         }
         .render()
         .unwrap()
     }
 
+    /// fmt_time() is the local time the message was sent,
+    /// or the offset into the stream like `1:02:03` for replayed chats
+    pub fn fmt_time(&self) -> String {
+        fmt_time(self.timestamp, self.stream_offset)
+    }
+
+    /// the fragments joined into plain text, emotes are shown as `:name:`
+    pub fn plain_text(&self) -> String {
+        self.fragments.iter().map(Fragment::plain_text).collect()
+    }
+
     /* cli_format() returns a UTF8 string of the message.
-     * for DGG chat if the message starts with a '>' it will make the message green */
+     * for DGG chat if the message starts with a '>' it will make the message green,
+     * otherwise every fragment is shown the way Fragment::cli_format() shows it */
     pub fn cli_format(&self) -> String {
-        let local_time: DateTime<Local> = DateTime::from(self.timestamp);
+        let plain_text = self.plain_text();
 
         let msg_text = match self.location {
-            ChatLocation::Dgg => {
-                if self.msg_text.starts_with('>') {
-                    self.msg_text.green()
-                } else {
-                    ColoredString::from(self.msg_text.as_str())
-                }
-            }
-            ChatLocation::YouTube => ColoredString::from(self.msg_text.as_str()),
-            ChatLocation::Kick => ColoredString::from(self.msg_text.as_str()),
+            ChatLocation::Dgg if plain_text.starts_with('>') => plain_text.green().to_string(),
+            _ => self.fragments.iter().map(Fragment::cli_format).collect(),
         };
 
         format!(
             "[{} {} {}] {}",
-            local_time.format("%H:%M"),
-            self.location.name(),
-            self.author,
+            self.fmt_time(),
+            self.source_name(),
+            self.author.name,
             msg_text
         )
     }
 }
+
+/// Something that happened in a chat that isn't a regular message
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ChatNotice {
+    /// the chat location where the notice originated from
+    pub location: ChatLocation,
+    /// the channel or room within the chat location
+    pub channel: String,
+    /// when the notice was sent, or received if the chat location doesn't say
+    pub timestamp: DateTime<Utc>,
+    /// how far into the stream the notice was shown, only known for replayed chats
+    #[serde(default)]
+    pub stream_offset: Option<Duration>,
+    pub kind: NoticeKind,
+    /// raw full notice as it was received
+    pub raw_full_msg: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum NoticeKind {
+    /// a moderator deleted the message with this id
+    MsgDeleted {
+        msg_id: String,
+    },
+    /// the message with this id was replaced, for example once youtube finished processing it
+    MsgReplaced {
+        msg_id: String,
+        msg: Box<ChatMsg>,
+    },
+    /// a source that follows a channel went from waiting to live or back
+    LiveState {
+        state: LiveState,
+    },
+    /// all messages of an author were removed, for example because they were banned or timed out
+    AuthorRemoved {
+        author: String,
+    },
+    /// `expires_at` is `None` for a permanent ban and when the chat location doesn't say
+    UserBanned {
+        username: String,
+        banned_by: Option<String>,
+        permanent: bool,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// `expires_at` is `None` when the chat location doesn't say
+    UserMuted {
+        username: String,
+        muted_by: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    UserUnbanned {
+        username: String,
+        unbanned_by: Option<String>,
+    },
+    /// sub only mode was turned on or off
+    SubOnly {
+        enabled: bool,
+        by: Option<String>,
+    },
+    /// an announcement to the whole chat
+    Broadcast {
+        msg_text: String,
+    },
+    /// a private message sent to us
+    Whisper {
+        from: String,
+        msg_text: String,
+    },
+    PollStarted {
+        question: String,
+        options: Vec<String>,
+    },
+    /// `results` are the options with their vote totals
+    PollEnded {
+        question: String,
+        results: Vec<(String, u64)>,
+    },
+    MsgPinned {
+        author: String,
+        msg_text: String,
+    },
+    /// `tier` is the name of the membership level, if the chat location has levels
+    Subscription {
+        username: String,
+        months: Option<u32>,
+        tier: Option<String>,
+        msg_text: Option<String>,
+    },
+    /// `recipients` can be empty when the chat location only says how many were gifted
+    GiftedSubs {
+        gifter: String,
+        count: u32,
+        recipients: Vec<String>,
+    },
+    /// a paid message (like a youtube super chat) or a paid sticker
+    Paid {
+        /// the id the chat location gave the paid message, so it can be deleted like a message
+        msg_id: Option<String>,
+        author: String,
+        /// the amount as shown by the chat location, for example: `CA$10.00`
+        amount_text: String,
+        amount: f64,
+        /// the currency symbol or code, for example: `CA$`
+        currency: String,
+        msg_text: Option<String>,
+        /// the name of the sticker for paid stickers
+        sticker: Option<String>,
+        /// ARGB colours the chat location highlights the message with
+        header_color: Option<u32>,
+        body_color: Option<u32>,
+    },
+    Host {
+        host: String,
+        viewers: u64,
+        msg_text: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum LiveState {
+    /// the channel isn't live, the source checks every now and then
+    Waiting,
+    Live {
+        video_id: String,
+    },
+    Ended {
+        video_id: String,
+    },
+}
+
+impl NoticeKind {
+    /// a short name of the kind, used as the html class
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoticeKind::MsgDeleted { .. } => "deleted",
+            NoticeKind::MsgReplaced { .. } => "replaced",
+            NoticeKind::AuthorRemoved { .. } => "ban",
+            NoticeKind::LiveState { .. } => "state",
+            NoticeKind::UserBanned { .. } => "ban",
+            NoticeKind::UserMuted { .. } => "ban",
+            NoticeKind::UserUnbanned { .. } => "unban",
+            NoticeKind::SubOnly { .. } => "mode",
+            NoticeKind::Broadcast { .. } => "broadcast",
+            NoticeKind::Whisper { .. } => "whisper",
+            NoticeKind::PollStarted { .. } => "poll",
+            NoticeKind::PollEnded { .. } => "poll",
+            NoticeKind::MsgPinned { .. } => "pin",
+            NoticeKind::Subscription { .. } => "sub",
+            NoticeKind::GiftedSubs { .. } => "sub",
+            NoticeKind::Paid { .. } => "paid",
+            NoticeKind::Host { .. } => "host",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            NoticeKind::MsgDeleted { msg_id } => format!("message {} was deleted", msg_id),
+            NoticeKind::MsgReplaced { msg, .. } => msg.plain_text(),
+            NoticeKind::LiveState { state } => match state {
+                LiveState::Waiting => "waiting for the stream to go live".to_string(),
+                LiveState::Live { video_id } => format!("stream {} is live", video_id),
+                LiveState::Ended { video_id } => format!("stream {} ended", video_id),
+            },
+            NoticeKind::AuthorRemoved { author } => {
+                format!("messages from {} were removed", author)
+            }
+            NoticeKind::UserBanned {
+                username,
+                banned_by,
+                permanent,
+                expires_at,
+            } => {
+                let by = banned_by
+                    .as_ref()
+                    .map(|mod_name| format!(" by {}", mod_name))
+                    .unwrap_or_default();
+                match expires_at {
+                    _ if *permanent => format!("{} was permanently banned{}", username, by),
+                    Some(expires_at) => {
+                        let local_time: DateTime<Local> = DateTime::from(*expires_at);
+                        format!(
+                            "{} was banned{} until {}",
+                            username,
+                            by,
+                            local_time.format("%H:%M")
+                        )
+                    }
+                    None => format!("{} was banned{}", username, by),
+                }
+            }
+            NoticeKind::UserMuted {
+                username,
+                muted_by,
+                expires_at,
+            } => {
+                let by = muted_by
+                    .as_ref()
+                    .map(|mod_name| format!(" by {}", mod_name))
+                    .unwrap_or_default();
+                match expires_at {
+                    Some(expires_at) => {
+                        let local_time: DateTime<Local> = DateTime::from(*expires_at);
+                        format!(
+                            "{} was muted{} until {}",
+                            username,
+                            by,
+                            local_time.format("%H:%M")
+                        )
+                    }
+                    None => format!("{} was muted{}", username, by),
+                }
+            }
+            NoticeKind::UserUnbanned {
+                username,
+                unbanned_by,
+            } => match unbanned_by {
+                Some(mod_name) => format!("{} was unbanned by {}", username, mod_name),
+                None => format!("{} was unbanned", username),
+            },
+            NoticeKind::SubOnly { enabled, by } => {
+                let state = if *enabled { "on" } else { "off" };
+                match by {
+                    Some(mod_name) => format!("{} turned sub only mode {}", mod_name, state),
+                    None => format!("sub only mode is {}", state),
+                }
+            }
+            NoticeKind::Broadcast { msg_text } => msg_text.clone(),
+            NoticeKind::Whisper { from, msg_text } => {
+                format!("whisper from {}: {}", from, msg_text)
+            }
+            NoticeKind::PollStarted { question, options } => {
+                format!("poll: {} [{}]", question, options.join(", "))
+            }
+            NoticeKind::PollEnded { question, results } => {
+                let results = results
+                    .iter()
+                    .map(|(option, votes)| format!("{} {}", option, votes))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("poll ended: {} [{}]", question, results)
+            }
+            NoticeKind::MsgPinned { author, msg_text } => {
+                format!("pinned message from {}: {}", author, msg_text)
+            }
+            NoticeKind::Subscription {
+                username,
+                months,
+                tier,
+                msg_text,
+            } => {
+                let mut text = match months {
+                    Some(months) => format!("{} subscribed for {} months", username, months),
+                    None => format!("{} subscribed", username),
+                };
+                if let Some(tier) = tier {
+                    text.push_str(&format!(" ({})", tier));
+                }
+                if let Some(msg_text) = msg_text {
+                    text.push_str(": ");
+                    text.push_str(msg_text);
+                }
+                text
+            }
+            NoticeKind::GiftedSubs { gifter, count, .. } => {
+                format!("{} gifted {} subs", gifter, count)
+            }
+            NoticeKind::Paid {
+                author,
+                amount_text,
+                msg_text,
+                sticker,
+                ..
+            } => match (sticker, msg_text) {
+                (Some(sticker), _) => {
+                    format!("{} sent a {} sticker for {}", author, sticker, amount_text)
+                }
+                (None, Some(msg_text)) => format!("{} sent {}: {}", author, amount_text, msg_text),
+                (None, None) => format!("{} sent {}", author, amount_text),
+            },
+            NoticeKind::Host {
+                host,
+                viewers,
+                msg_text,
+            } => {
+                let mut text = format!("{} is hosting with {} viewers", host, viewers);
+                if let Some(msg_text) = msg_text {
+                    text.push_str(": ");
+                    text.push_str(msg_text);
+                }
+                text
+            }
+        }
+    }
+}
+
+/// fmt_time() is the local time of `timestamp` like `13:37`,
+/// or the offset into the stream like `1:02:03` when there is one
+fn fmt_time(timestamp: DateTime<Utc>, stream_offset: Option<Duration>) -> String {
+    match stream_offset {
+        Some(offset) => {
+            let secs = offset.as_secs();
+            format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        }
+        None => {
+            let local_time: DateTime<Local> = DateTime::from(timestamp);
+            local_time.format("%H:%M").to_string()
+        }
+    }
+}
+
+impl ChatNotice {
+    /// fmt_time() is like ChatMsg::fmt_time()
+    pub fn fmt_time(&self) -> String {
+        fmt_time(self.timestamp, self.stream_offset)
+    }
+
+    /// the location and channel of the notice, for example: `kick/xqc`
+    pub fn source_name(&self) -> String {
+        format!("{}/{}", self.location.name(), self.channel)
+    }
+
+    /// the ARGB colour of paid messages highlighted by the chat location,
+    /// the header colour when there is one as it is the colour of the amount, in the terminal and the web ui
+    pub fn background_color(&self) -> Option<u32> {
+        match &self.kind {
+            NoticeKind::Paid {
+                header_color,
+                body_color,
+                ..
+            } => header_color.or(*body_color),
+            _ => None,
+        }
+    }
+
+    /// the id of the notice itself for notices that can be deleted, like paid messages
+    pub fn msg_id(&self) -> Option<&str> {
+        match &self.kind {
+            NoticeKind::Paid { msg_id, .. } => msg_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// the id of the message this notice retracts, if any
+    pub fn target_msg_id(&self) -> Option<&str> {
+        match &self.kind {
+            NoticeKind::MsgDeleted { msg_id } => Some(msg_id.as_str()),
+            NoticeKind::MsgReplaced { msg_id, .. } => Some(msg_id.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn to_html(&self) -> String {
+        if let NoticeKind::MsgReplaced { msg_id, msg } = &self.kind {
+            return ReplacedTemplate {
+                source: &self.source_name(),
+                target_id: msg_id,
+                class: self.location.name(),
+                msg_html: msg.to_html(),
+            }
+            .render()
+            .unwrap();
+        }
+
+        let source_name = self.source_name();
+        let fmt_time = self.fmt_time();
+        let text = self.kind.describe();
+
+        NoticeTemplate {
+            msg_id: self.msg_id(),
+            target_id: self.target_msg_id(),
+            background_color: self
+                .background_color()
+                .map(|color| format!("#{:06x}", color & 0xffffff)),
+            text: text.as_str(),
+            location: source_name.as_str(),
+            fmt_time: fmt_time.as_str(),
+            class: self.location.name(),
+            kind: self.kind.name(),
+        }
+        .render()
+        .unwrap()
+    }
+
+    /* cli_format() returns a UTF8 string of the notice.
+     * paid messages get the background colour youtube gives them, subs are highlighted in yellow, moderation actions in red and announcements in magenta */
+    pub fn cli_format(&self) -> String {
+        let text = self.kind.describe();
+        let text = match self.kind {
+            NoticeKind::Paid { .. } => match self.background_color() {
+                Some(color) => {
+                    let [_, r, g, b] = color.to_be_bytes();
+                    text.black().on_truecolor(r, g, b)
+                }
+                None => text.black().on_yellow(),
+            },
+            NoticeKind::Subscription { .. } | NoticeKind::GiftedSubs { .. } => text.yellow(),
+            NoticeKind::MsgReplaced { .. } => ColoredString::from(text.as_str()),
+            NoticeKind::LiveState { .. } => text.dimmed(),
+            NoticeKind::MsgDeleted { .. }
+            | NoticeKind::AuthorRemoved { .. }
+            | NoticeKind::UserBanned { .. }
+            | NoticeKind::UserMuted { .. } => text.red(),
+            NoticeKind::UserUnbanned { .. } | NoticeKind::SubOnly { .. } => text.green(),
+            NoticeKind::Broadcast { .. } | NoticeKind::Whisper { .. } => text.magenta(),
+            NoticeKind::PollStarted { .. }
+            | NoticeKind::PollEnded { .. }
+            | NoticeKind::MsgPinned { .. }
+            | NoticeKind::Host { .. } => text.cyan(),
+        };
+
+        format!("[{} {}] {}", self.fmt_time(), self.source_name(), text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paid(header_color: Option<u32>, body_color: Option<u32>) -> ChatNotice {
+        ChatNotice {
+            location: ChatLocation::YouTube,
+            channel: "destiny".to_string(),
+            timestamp: Utc::now(),
+            stream_offset: None,
+            kind: NoticeKind::Paid {
+                msg_id: Some("paid".to_string()),
+                author: "Bob".to_string(),
+                amount_text: "$20.00".to_string(),
+                amount: 20.0,
+                currency: "$".to_string(),
+                msg_text: None,
+                sticker: None,
+                header_color,
+                body_color,
+            },
+            raw_full_msg: String::new(),
+        }
+    }
+
+    #[test]
+    fn paid_colors() {
+        colored::control::set_override(true);
+        let both = paid(Some(0xff1de9b6), Some(0xff00bfa5));
+        assert_eq!(both.background_color(), Some(0xff1de9b6));
+        assert!(both.to_html().contains("background-color: #1de9b6"));
+        assert!(both.cli_format().contains("\x1b[48;2;29;233;182;30m"));
+
+        let body = paid(None, Some(0xff00bfa5));
+        assert!(body.to_html().contains("background-color: #00bfa5"));
+        assert!(body.cli_format().contains("\x1b[48;2;0;191;165;30m"));
+
+        let none = paid(None, None);
+        assert_eq!(none.background_color(), None);
+        assert!(!none.to_html().contains("background-color"));
+    }
+
+    #[test]
+    fn html_targets_carry_the_source() {
+        let deleted = ChatNotice {
+            kind: NoticeKind::MsgDeleted {
+                msg_id: "abc".to_string(),
+            },
+            ..paid(None, None)
+        };
+        assert!(deleted
+            .to_html()
+            .contains(r#"data-source="youtube/destiny" data-target-id="abc""#));
+        // paid messages can be deleted as well
+        assert!(paid(None, None)
+            .to_html()
+            .contains(r#"data-source="youtube/destiny" data-msg-id="paid""#));
+    }
+
+    fn text(text: &str) -> Fragment {
+        Fragment::Text(text.to_string())
+    }
+
+    fn link(url: &str) -> Fragment {
+        Fragment::Link {
+            url: url.to_string(),
+            text: url.to_string(),
+        }
+    }
+
+    #[test]
+    fn links_and_mentions() {
+        assert_eq!(
+            Fragment::parse_text("see https://example.com."),
+            [text("see "), link("https://example.com"), text(".")]
+        );
+        assert_eq!(
+            Fragment::parse_text("(see https://example.com)"),
+            [text("(see "), link("https://example.com"), text(")")]
+        );
+        assert_eq!(
+            Fragment::parse_text("https://en.wikipedia.org/wiki/Rust_(programming_language)"),
+            [link(
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)"
+            )]
+        );
+        assert_eq!(
+            Fragment::parse_text(
+                "(see https://en.wikipedia.org/wiki/Rust_(programming_language))."
+            ),
+            [
+                text("(see "),
+                link("https://en.wikipedia.org/wiki/Rust_(programming_language)"),
+                text(")."),
+            ]
+        );
+        assert_eq!(Fragment::parse_text("https://"), [text("https://")]);
+
+        assert_eq!(
+            Fragment::parse_text("hi @bob, @ a@b"),
+            [
+                text("hi "),
+                Fragment::Mention("bob".to_string()),
+                text(", @ a@b"),
+            ]
+        );
+        assert_eq!(
+            Fragment::parse_text("@bob!"),
+            [Fragment::Mention("bob".to_string()), text("!")]
+        );
+    }
+}
@@ -21,3 +21,23 @@ pub mod json_string {
         serde_json::from_str(&j).map_err(de::Error::custom)
     }
 }
+
+/// Deserializes ids that are sometimes sent as a number and sometimes as a string into a `String`
+pub mod string_or_number {
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde_json::Value;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::String(s) => Ok(s),
+            Value::Number(n) => Ok(n.to_string()),
+            other => Err(de::Error::custom(format!(
+                "expected a string or a number, got {}",
+                other
+            ))),
+        }
+    }
+}
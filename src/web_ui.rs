@@ -1,33 +1,108 @@
-use futures_util::SinkExt;
+use futures_util::{SinkExt, StreamExt};
 use log::{info, warn};
+use rand::Rng;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast::Receiver;
 use tokio::time::sleep;
-use tokio_tungstenite::accept_async;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::http::StatusCode;
 use tokio_tungstenite::tungstenite::Message;
 
-use crate::types::ChatMsg;
+use crate::dgg::DggSender;
+use crate::types::ChatEvent;
 
-pub async fn main(rx: Receiver<ChatMsg>) {
-    let addr = "127.0.0.1:8080".to_string();
-    let try_socket = TcpListener::bind(&addr).await;
+const ADDR: &str = "127.0.0.1:8080";
+
+/// the web ui page, `WEB_UI_TOKEN` in it is replaced with the token of the run
+const CHAT_HTML: &str = include_str!("../chat.html");
+
+/// `dgg` sends the text messages the browser sends us to the dgg chat
+pub async fn main(rx: Receiver<ChatEvent>, dgg: Option<DggSender>) {
+    let try_socket = TcpListener::bind(ADDR).await;
     let listener = try_socket.expect("Failed to bind");
-    info!("Listening on: {}", addr);
+    info!("Listening on: {}", ADDR);
+    println!("Web ui at http://{}/", ADDR);
+
+    // websockets are only accepted from the page served here, which knows the token of this run,
+    // so other web pages the user visits can't read the chat or send to dgg as them
+    let token: Arc<str> = new_token().into();
 
     while let Ok((stream, _)) = listener.accept().await {
-        tokio::spawn(accept_connection(stream, rx.resubscribe()));
+        tokio::spawn(accept_connection(
+            stream,
+            rx.resubscribe(),
+            dgg.clone(),
+            token.clone(),
+        ));
     }
 }
 
-async fn accept_connection(stream: TcpStream, mut rx: Receiver<ChatMsg>) {
+/// new_token() is a random 32 hex digit token
+fn new_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32)
+        .map(|_| format!("{:x}", rng.gen_range(0..16u8)))
+        .collect()
+}
+
+/// the size of the peek at a request, enough for the headers browsers send
+const PEEK_SIZE: usize = 4096;
+
+async fn accept_connection(
+    stream: TcpStream,
+    mut rx: Receiver<ChatEvent>,
+    dgg: Option<DggSender>,
+    token: Arc<str>,
+) {
     let addr = stream
         .peer_addr()
         .expect("connected streams should have a peer address");
-
     info!("Peer address: {}", addr);
-    let mut ws_stream = accept_async(stream).await.expect("Failed to accept");
+
+    // the page and the websocket share the port, plain http requests get the page
+    let mut head = [0; PEEK_SIZE];
+    let Ok(len) = stream.peek(&mut head).await else {
+        return;
+    };
+    let head = String::from_utf8_lossy(&head[..len]).to_lowercase();
+    if !head.contains("upgrade: websocket") {
+        if let Err(err) = serve_page(stream, &head, len, &token).await {
+            warn!("Can't serve the web ui to {}, {}", addr, err);
+        }
+        return;
+    }
+
+    let ws_stream = match accept_hdr_async(stream, HandshakeCheck { token }).await {
+        Ok(ws_stream) => ws_stream,
+        Err(err) => {
+            warn!("Refused websocket from {}, {}", addr, err);
+            return;
+        }
+    };
     info!("New WebSocket connection: {}", addr);
+    let (mut ws_sink, mut ws_source) = ws_stream.split();
+
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = ws_source.next().await {
+            let Message::Text(text) = msg else {
+                continue;
+            };
+            match &dgg {
+                Some(dgg) => {
+                    if let Err(err) = dgg.send_message(&text).await {
+                        warn!("Can't send reply from {}, {}", addr, err);
+                    }
+                }
+                None => warn!("Got a reply from {} but no dgg chat is logged in", addr),
+            }
+        }
+    });
 
     // for some fucking reason this is needed
     loop {
@@ -42,10 +117,75 @@ async fn accept_connection(stream: TcpStream, mut rx: Receiver<ChatMsg>) {
             Ok(msg) => {
                 let html_text = msg.to_html();
                 let msg = Message::Text(html_text);
-                ws_stream.send(msg).await.unwrap();
+                ws_sink.send(msg).await.unwrap();
                 // 😀
             }
             Err(recv_error) => warn!("Printer got recv error, {}", recv_error),
         }
     }
 }
+
+/// serve_page() answers a plain http request with the web ui,
+/// `head` is the lowercased start of the request and `len` the number of bytes peeked for it
+async fn serve_page(
+    mut stream: TcpStream,
+    head: &str,
+    len: usize,
+    token: &str,
+) -> std::io::Result<()> {
+    // the request has to be read before the answer, its body is never looked at,
+    // the lossy lowercased head can be longer than the bytes it was made from
+    let mut request = vec![0; len];
+    stream.read_exact(&mut request).await?;
+
+    let path = head.split(' ').nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/" => ("200 OK", CHAT_HTML.replace("WEB_UI_TOKEN", token)),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Only lets websockets from the served page through,
+/// their `Origin` is this server and the url has the token of the run
+struct HandshakeCheck {
+    token: Arc<str>,
+}
+
+impl Callback for HandshakeCheck {
+    fn on_request(self, req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+        let origin = req
+            .headers()
+            .get("origin")
+            .and_then(|origin| origin.to_str().ok())
+            .unwrap_or_default();
+        let port = ADDR.rsplit(':').next().unwrap_or_default();
+        let own_origin = [
+            format!("http://{}", ADDR),
+            format!("http://localhost:{}", port),
+        ]
+        .contains(&origin.to_string());
+
+        let token_param = format!("token={}", self.token);
+        let has_token = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .any(|param| param == token_param);
+
+        if own_origin && has_token {
+            return Ok(res);
+        }
+        let mut forbidden = ErrorResponse::new(Some("forbidden".to_string()));
+        *forbidden.status_mut() = StatusCode::FORBIDDEN;
+        Err(forbidden)
+    }
+}
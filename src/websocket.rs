@@ -0,0 +1,267 @@
+use crate::config::WebsocketChat;
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{Author, ChatEvent, ChatLocation, ChatMsg, Fragment};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use jsonpath_rust::JsonPathInst;
+use log::{debug, error, info, warn};
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+/// unix timestamps above this are taken to be in milliseconds, it is in the year 5138 as seconds
+const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+pub async fn main(tx: Sender<ChatEvent>, chat: WebsocketChat, health: Health) {
+    let name = format!("websocket {}", chat.name);
+    let paths = match MessagePaths::new(&chat) {
+        Ok(paths) => Arc::new(paths),
+        Err(err) => {
+            error!("{} can't start, {}", name, err);
+            health.finished();
+            return;
+        }
+    };
+    supervise(&name, &health, Backoff::default(), || {
+        connect(tx.clone(), chat.clone(), paths.clone())
+    })
+    .await
+}
+
+async fn connect(
+    tx: Sender<ChatEvent>,
+    chat: WebsocketChat,
+    paths: Arc<MessagePaths>,
+) -> Result<(), SourceError> {
+    debug!("Connecting to websocket {}", chat.url);
+    let (mut socket, _) = connect_async(chat.url.as_str()).await?;
+    for frame in &chat.subscribe {
+        socket.send(Message::Text(frame.clone())).await?;
+    }
+    info!("connected to websocket chat {}", chat.name);
+
+    loop {
+        let msg = socket.next().await.ok_or(SourceError::Closed)??;
+        if !msg.is_text() {
+            continue;
+        }
+        let raw_msg_text = msg.to_text()?;
+        if raw_msg_text.is_empty() {
+            continue;
+        }
+        debug!("{}", raw_msg_text);
+
+        let frame = match serde_json::from_str::<Value>(raw_msg_text) {
+            Ok(frame) => frame,
+            Err(err) => {
+                warn!(
+                    "Can't parse websocket {} frame `{}`, {}",
+                    chat.name, raw_msg_text, err
+                );
+                continue;
+            }
+        };
+        for chat_msg in paths.chat_msgs(&frame, &chat.name, raw_msg_text) {
+            tx.send(chat_msg.into()).await?;
+        }
+    }
+}
+
+/// The JSONPath expressions of a `WebsocketChat`, parsed once
+struct MessagePaths {
+    messages: Option<JsonPathInst>,
+    author: JsonPathInst,
+    text: JsonPathInst,
+    timestamp: Option<JsonPathInst>,
+    id: Option<JsonPathInst>,
+}
+
+impl MessagePaths {
+    fn new(chat: &WebsocketChat) -> Result<Self, String> {
+        let parse = |path: &str| {
+            JsonPathInst::from_str(path).map_err(|err| format!("invalid path `{}`, {}", path, err))
+        };
+        let parse_optional = |path: &Option<String>| path.as_deref().map(parse).transpose();
+        Ok(MessagePaths {
+            messages: parse_optional(&chat.messages)?,
+            author: parse(&chat.author)?,
+            text: parse(&chat.text)?,
+            timestamp: parse_optional(&chat.timestamp)?,
+            id: parse_optional(&chat.id)?,
+        })
+    }
+
+    /// chat_msgs() picks the messages out of a frame, those without text are skipped
+    fn chat_msgs(&self, frame: &Value, channel: &str, raw_msg_text: &str) -> Vec<ChatMsg> {
+        let messages: Vec<Value> = match &self.messages {
+            Some(messages) => messages
+                .find_slice(frame)
+                .into_iter()
+                .map(|msg| msg.clone())
+                .collect(),
+            None => vec![frame.clone()],
+        };
+
+        messages
+            .iter()
+            .filter_map(|msg| {
+                let text = find_string(&self.text, msg)?;
+                Some(ChatMsg {
+                    id: self.id.as_ref().and_then(|id| find_string(id, msg)),
+                    location: ChatLocation::Websocket,
+                    channel: channel.to_string(),
+                    timestamp: self
+                        .timestamp
+                        .as_ref()
+                        .and_then(|timestamp| find_timestamp(timestamp, msg))
+                        .unwrap_or_else(Utc::now),
+                    stream_offset: None,
+                    fragments: Fragment::parse_text(&text),
+                    msg_text: text,
+                    author: Author {
+                        name: find_string(&self.author, msg).unwrap_or_default(),
+                        ..Author::default()
+                    },
+                    raw_full_msg: raw_msg_text.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// find_string() is the first match of `path` as a string, numbers and bools are written out
+fn find_string(path: &JsonPathInst, msg: &Value) -> Option<String> {
+    let found = path.find_slice(msg).into_iter().next()?;
+    match &*found {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// find_timestamp() reads an RFC 3339 string or a unix timestamp in seconds or milliseconds,
+/// unix timestamps can have a fraction like `1700000000.5`
+fn find_timestamp(path: &JsonPathInst, msg: &Value) -> Option<DateTime<Utc>> {
+    let found = path.find_slice(msg).into_iter().next()?;
+    let unix = match &*found {
+        Value::String(s) => match s.parse::<f64>() {
+            Ok(unix) => unix,
+            Err(_) => return s.parse().ok(),
+        },
+        Value::Number(n) => n.as_f64()?,
+        _ => return None,
+    };
+    if !unix.is_finite() {
+        return None;
+    }
+    let millis = if unix.abs() >= MILLIS_THRESHOLD as f64 {
+        unix
+    } else {
+        unix * 1000.0
+    };
+    let timestamp = NaiveDateTime::from_timestamp_millis(millis.round() as i64)?;
+    Some(DateTime::from_naive_utc_and_offset(timestamp, Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(chat: Value) -> MessagePaths {
+        let chat: WebsocketChat = serde_json::from_value(chat).unwrap();
+        MessagePaths::new(&chat).unwrap()
+    }
+
+    fn path(path: &str) -> JsonPathInst {
+        JsonPathInst::from_str(path).unwrap()
+    }
+
+    #[test]
+    fn whole_frame_is_the_message() {
+        let paths = paths(serde_json::json!({
+            "name": "lobby",
+            "url": "wss://example.com/chat",
+            "author": "$.user.name",
+            "text": "$.text",
+            "id": "$.id",
+        }));
+        let frame = serde_json::json!({ "id": 7, "user": { "name": "Bob" }, "text": "hi @alice" });
+
+        let msgs = paths.chat_msgs(&frame, "lobby", "raw");
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].id.as_deref(), Some("7"));
+        assert_eq!(msgs[0].author.name, "Bob");
+        assert_eq!(msgs[0].msg_text, "hi @alice");
+        assert_eq!(msgs[0].fragments[1], Fragment::Mention("alice".to_string()));
+        assert_eq!(msgs[0].channel, "lobby");
+        assert_eq!(msgs[0].raw_full_msg, "raw");
+
+        // frames without text, like acks and presence updates, aren't messages
+        let ack = serde_json::json!({ "type": "ack", "id": 8 });
+        assert!(paths.chat_msgs(&ack, "lobby", "raw").is_empty());
+    }
+
+    #[test]
+    fn messages_path() {
+        let paths = paths(serde_json::json!({
+            "name": "lobby",
+            "url": "wss://example.com/chat",
+            "messages": "$.batch[*]",
+            "author": "$.from",
+            "text": "$.body",
+            "timestamp": "$.sent",
+        }));
+        let frame = serde_json::json!({
+            "batch": [
+                { "from": "a", "body": "one", "sent": "2023-11-22T10:00:00Z" },
+                { "from": "b", "joined": true },
+                { "from": "c", "body": "three", "sent": 1700647200 },
+            ]
+        });
+
+        let msgs = paths.chat_msgs(&frame, "lobby", "raw");
+        let texts: Vec<_> = msgs.iter().map(|msg| msg.msg_text.as_str()).collect();
+        assert_eq!(texts, ["one", "three"]);
+        let sent: DateTime<Utc> = "2023-11-22T10:00:00Z".parse().unwrap();
+        assert!(msgs.iter().all(|msg| msg.timestamp == sent));
+        assert!(msgs.iter().all(|msg| msg.id.is_none()));
+    }
+
+    #[test]
+    fn strings() {
+        let msg = serde_json::json!({ "s": "x", "n": 42, "f": 1.5, "b": true, "o": {}, "a": [] });
+        assert_eq!(find_string(&path("$.s"), &msg).as_deref(), Some("x"));
+        assert_eq!(find_string(&path("$.n"), &msg).as_deref(), Some("42"));
+        assert_eq!(find_string(&path("$.f"), &msg).as_deref(), Some("1.5"));
+        assert_eq!(find_string(&path("$.b"), &msg).as_deref(), Some("true"));
+        assert_eq!(find_string(&path("$.o"), &msg), None);
+        assert_eq!(find_string(&path("$.missing"), &msg), None);
+    }
+
+    #[test]
+    fn timestamps() {
+        let sent: DateTime<Utc> = "2023-11-22T10:00:00Z".parse().unwrap();
+        let half = chrono::Duration::milliseconds(500);
+        let cases = [
+            (serde_json::json!("2023-11-22T10:00:00Z"), Some(sent)),
+            (serde_json::json!("2023-11-22T11:00:00+01:00"), Some(sent)),
+            (serde_json::json!(1700647200), Some(sent)),
+            (serde_json::json!("1700647200"), Some(sent)),
+            (serde_json::json!(1700647200000u64), Some(sent)),
+            (serde_json::json!(1700647200.5), Some(sent + half)),
+            (serde_json::json!("1700647200.5"), Some(sent + half)),
+            (serde_json::json!(1700647200000.0), Some(sent)),
+            (serde_json::json!("yesterday"), None),
+            (serde_json::json!("NaN"), None),
+            (serde_json::json!(true), None),
+        ];
+        for (value, expected) in cases {
+            let msg = serde_json::json!({ "sent": value });
+            assert_eq!(find_timestamp(&path("$.sent"), &msg), expected, "{}", value);
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use jsonpath_rust::JsonPathQuery;
+use crate::supervisor::SourceError;
 use reqwest::header::{self, HeaderMap};
+use reqwest::{Client, Proxy};
 use scraper::{Html, Selector};
 use serde_json::{Map, Value};
+use url::Url;
 
 #[derive(Debug)]
 pub struct YtCfg {
@@ -13,80 +15,244 @@ pub struct YtCfg {
     pub chat_id: String,
     /// sort of like a next page token
     pub first_continuation: String,
+    /// the version of the web client that served the page, for example: 2.20240111.00.00
+    pub client_version: Option<String>,
+    /// the `context` every innertube request has to send, as the page set it up
+    pub innertube_context: Option<Value>,
 }
 
-pub async fn get_ytcfg(channel_name: &str) -> Option<YtCfg> {
+/// build_client() builds the client every request of a youtube source goes through,
+/// it keeps connections alive and the cookies youtube sets, like the consent cookie.
+/// the proxy falls back to the `HTTP_PROXY` and `HTTPS_PROXY` env vars
+pub fn build_client(proxy: Option<&str>) -> Result<Client, SourceError> {
+    let mut builder = Client::builder().cookie_store(true);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// get_ytcfg() reads the `YtCfg` of the live stream of a channel,
+/// `None` if the channel isn't live or the stream has no live chat
+pub async fn get_ytcfg(client: &Client, channel_name: &str) -> Result<Option<YtCfg>, SourceError> {
     let url = format!("https://www.youtube.com/@{}/live", channel_name);
+    let res_text = get_page(client, &url).await?;
+
+    let document = Html::parse_document(&res_text);
+
+    // when the channel isn't live `/live` shows the channel page, which has no live stream id
+    let Some(video_id) = get_live_stream_id(&document) else {
+        return Ok(None);
+    };
+    parse_watch_page(&document, video_id)
+}
+
+/// get_ytcfg_for_replay() reads the `YtCfg` of the replay chat from the watch page of a finished stream,
+/// `None` if the video has no chat replay
+pub async fn get_ytcfg_for_replay(
+    client: &Client,
+    video_id: &str,
+) -> Result<Option<YtCfg>, SourceError> {
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+    let res_text = get_page(client, &url).await?;
+
+    let document = Html::parse_document(&res_text);
+
+    parse_watch_page(&document, video_id.to_string())
+}
+
+async fn get_page(client: &Client, url: &str) -> Result<String, SourceError> {
     let headers = get_headers();
 
-    let res = reqwest::Client::new()
+    let res_text = client
         .get(url)
         .headers(headers)
         .send()
-        .await
-        .ok()?;
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(res_text)
+}
 
-    let res_text = res.text().await.ok()?;
+/// parse_watch_page() reads the `YtCfg` of the chat embedded in a watch page,
+/// `None` if the page has no chat
+fn parse_watch_page(document: &Html, video_id: String) -> Result<Option<YtCfg>, SourceError> {
+    let raw_ytinitaldata = get_raw_ytinitaldata(document).ok_or(SourceError::Other(
+        "no ytInitialData on the watch page".to_string(),
+    ))?;
+    let raw_ytcfg = get_raw_ytcfg(document)
+        .ok_or(SourceError::Other("no ytcfg on the watch page".to_string()))?;
 
-    let document = Html::parse_document(&res_text);
+    let Some(continuation) = raw_ytinitaldata
+        .get("contents")
+        .and_then(|v| v.get("twoColumnWatchNextResults"))
+        .and_then(|v| v.get("conversationBar"))
+        .and_then(|v| v.get("liveChatRenderer"))
+        .and_then(get_live_chat_continuation)
+    else {
+        return Ok(None);
+    };
+
+    from_raw_ytcfg(&Value::Object(raw_ytcfg), video_id, continuation).map(Some)
+}
+
+/// get_ytcfg_for_video() reads the `YtCfg` from the popout chat page of a video,
+/// this works for unlisted streams and channels with several streams at once,
+/// `None` if the video has no live chat
+pub async fn get_ytcfg_for_video(
+    client: &Client,
+    video_id: &str,
+) -> Result<Option<YtCfg>, SourceError> {
+    let url = format!(
+        "https://www.youtube.com/live_chat?is_popout=1&v={}",
+        video_id
+    );
+    let res_text = get_page(client, &url).await?;
+
+    let raw_ytcfg = find_json_after(&res_text, "ytcfg.set(").ok_or(SourceError::Other(
+        "no ytcfg on the live chat page".to_string(),
+    ))?;
+    let raw_ytinitaldata = find_json_after(&res_text, r#"window["ytInitialData"] = "#)
+        .or_else(|| find_json_after(&res_text, "var ytInitialData = "))
+        .ok_or(SourceError::Other(
+            "no ytInitialData on the live chat page".to_string(),
+        ))?;
 
-    let raw_ytinitaldata = get_raw_ytinitaldata(&document).unwrap();
-    let raw_ytcfg = get_raw_ytcfg(&document).unwrap();
+    let Some(continuation) = raw_ytinitaldata
+        .get("contents")
+        .and_then(|v| v.get("liveChatRenderer"))
+        .and_then(get_live_chat_continuation)
+    else {
+        return Ok(None);
+    };
 
-    let continuation = get_continuation(serde_json::to_value(&raw_ytinitaldata).unwrap()).unwrap();
+    from_raw_ytcfg(&raw_ytcfg, video_id.to_string(), continuation).map(Some)
+}
 
-    let video_id = get_live_stream_id(&document).unwrap();
+fn from_raw_ytcfg(
+    raw_ytcfg: &Value,
+    video_id: String,
+    first_continuation: String,
+) -> Result<YtCfg, SourceError> {
     let chat_id = raw_ytcfg
         .get("INNERTUBE_API_KEY")
-        .unwrap()
-        .as_str()
-        .unwrap()
+        .and_then(|v| v.as_str())
+        .ok_or(SourceError::Other(
+            "no INNERTUBE_API_KEY in ytcfg".to_string(),
+        ))?
         .to_string();
+    let client_version = raw_ytcfg
+        .get("INNERTUBE_CLIENT_VERSION")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string());
+    let innertube_context = raw_ytcfg.get("INNERTUBE_CONTEXT").cloned();
 
-    Some(YtCfg {
+    Ok(YtCfg {
         video_id,
         chat_id,
-        first_continuation: continuation,
+        first_continuation,
+        client_version,
+        innertube_context,
     })
 }
 
-fn get_continuation(raw_ytinitaldata: Value) -> Option<String> {
-
-    let mut continuation_id: Option<String> = None;
-    let sub_menu_items=  raw_ytinitaldata.path("$.contents.twoColumnWatchNextResults.conversationBar.liveChatRenderer.header.liveChatHeaderRenderer.viewSelector.sortFilterSubMenuRenderer.subMenuItems[*]").unwrap();
-    
-    for chat_continuation in sub_menu_items.as_array().unwrap() {
-        let chat_continuation = chat_continuation.as_object().unwrap();
-        let current_continuation_id = chat_continuation
-            .get("continuation")
-            .unwrap()
-            .as_object()
-            .unwrap()
-            .get("reloadContinuationData")
-            .unwrap()
-            .as_object()
-            .unwrap()
+/// get_live_chat_continuation() returns the continuation of the full "Live chat" (or "Live chat replay") view,
+/// or the continuation of the default "Top chat" view if there is no view selector
+fn get_live_chat_continuation(live_chat_renderer: &Value) -> Option<String> {
+    let sub_menu_items = live_chat_renderer
+        .get("header")
+        .and_then(|v| v.get("liveChatHeaderRenderer"))
+        .and_then(|v| v.get("viewSelector"))
+        .and_then(|v| v.get("sortFilterSubMenuRenderer"))
+        .and_then(|v| v.get("subMenuItems"))
+        .and_then(|v| v.as_array());
+    for sub_menu_item in sub_menu_items.into_iter().flatten() {
+        let title = sub_menu_item.get("title").and_then(|v| v.as_str());
+        if !matches!(title, Some("Live chat" | "Live chat replay")) {
+            continue;
+        }
+        let continuation = sub_menu_item
             .get("continuation")
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_string();
-        if chat_continuation.get("title").unwrap().as_str().unwrap() == "Live chat" {
-            continuation_id = Some(current_continuation_id);
+            .and_then(|v| v.get("reloadContinuationData"))
+            .and_then(|v| v.get("continuation"))
+            .and_then(|v| v.as_str());
+        if let Some(continuation) = continuation {
+            return Some(continuation.to_string());
         }
     }
 
-    continuation_id
+    let continuation = live_chat_renderer.get("continuations")?.get(0)?;
+    [
+        "invalidationContinuationData",
+        "timedContinuationData",
+        "reloadContinuationData",
+    ]
+    .iter()
+    .find_map(|key| continuation.get(key)?.get("continuation")?.as_str())
+    .map(|continuation| continuation.to_string())
+}
+
+/// find_json_after() parses the JSON value that directly follows `marker` in a page
+fn find_json_after(page: &str, marker: &str) -> Option<Value> {
+    let start = page.find(marker)? + marker.len();
+    serde_json::Deserializer::from_str(&page[start..])
+        .into_iter::<Value>()
+        .next()?
+        .ok()
+}
+
+/// parse_video_id() accepts a video id or a youtube url like
+/// `https://www.youtube.com/watch?v={id}`, `https://youtu.be/{id}` or `https://www.youtube.com/live/{id}`
+pub fn parse_video_id(video: &str) -> Option<String> {
+    let video = video.trim();
+    if is_video_id(video) {
+        return Some(video.to_string());
+    }
+
+    let url = Url::parse(video)
+        .or_else(|_| Url::parse(&format!("https://{}", video)))
+        .ok()?;
+    let host = url
+        .host_str()?
+        .trim_start_matches("www.")
+        .trim_start_matches("m.");
+
+    let video_id = match host {
+        "youtu.be" => url.path_segments()?.next().map(|id| id.to_string()),
+        "youtube.com" => {
+            let mut path_segments = url.path_segments()?;
+            match path_segments.next()? {
+                "watch" | "live_chat" => url
+                    .query_pairs()
+                    .find(|(key, _)| key == "v")
+                    .map(|(_, id)| id.to_string()),
+                "live" | "shorts" | "embed" | "v" => path_segments.next().map(|id| id.to_string()),
+                _ => None,
+            }
+        }
+        _ => None,
+    }?;
+
+    is_video_id(&video_id).then_some(video_id)
+}
+
+/// video ids are 11 characters of `[A-Za-z0-9_-]`
+fn is_video_id(video_id: &str) -> bool {
+    video_id.len() == 11
+        && video_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
 }
 
 fn get_live_stream_id(document: &Html) -> Option<String> {
     let selector = Selector::parse("head > link[rel=canonical]").ok()?;
 
-    let canonical_link = document.select(&selector).nth(0)?.attr("href")?;
+    let canonical_link = document.select(&selector).next()?.attr("href")?;
 
-    let video_id = canonical_link.split("v=").collect::<Vec<&str>>()[1].to_string();
+    let (_, video_id) = canonical_link.split_once("v=")?;
 
-    Some(video_id)
+    Some(video_id.to_string())
 }
 
 fn get_raw_ytcfg(document: &Html) -> Option<Map<String, Value>> {
@@ -96,13 +262,10 @@ fn get_raw_ytcfg(document: &Html) -> Option<Map<String, Value>> {
         if elm_text.contains("(function() {window.ytplayer={};") {
             let encoded_raw_ytcfg = elm_text
                 .split("ytcfg.set(")
-                .nth(1)
-                .unwrap()
+                .nth(1)?
                 .split("); window.ytcfg.obfuscatedData_")
-                .nth(0)
-                .unwrap();
-            let raw_ytcfg = serde_json::from_str::<Map<String, Value>>(encoded_raw_ytcfg).unwrap();
-            return Some(raw_ytcfg);
+                .next()?;
+            return serde_json::from_str::<Map<String, Value>>(encoded_raw_ytcfg).ok();
         }
     }
     None
@@ -115,13 +278,10 @@ fn get_raw_ytinitaldata(document: &Html) -> Option<Map<String, Value>> {
         if elm_text.contains("var ytInitialData = ") {
             let encoded_raw_ytcfg = elm_text
                 .split("var ytInitialData = ")
-                .nth(1)
-                .unwrap()
+                .nth(1)?
                 .split(";</script>")
-                .nth(0)
-                .unwrap();
-            let raw_ytcfg = serde_json::from_str::<Map<String, Value>>(encoded_raw_ytcfg).unwrap();
-            return Some(raw_ytcfg);
+                .next()?;
+            return serde_json::from_str::<Map<String, Value>>(encoded_raw_ytcfg).ok();
         }
     }
     None
@@ -146,3 +306,69 @@ fn get_headers() -> HeaderMap {
     headers.insert("Connection", "keep-alive".parse().unwrap());
     headers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_ids() {
+        let cases = [
+            ("jfNfCLB8Bm4", Some("jfNfCLB8Bm4")),
+            ("  jfNfCLB8Bm4\n", Some("jfNfCLB8Bm4")),
+            ("a-b_c-d_e-f", Some("a-b_c-d_e-f")),
+            (
+                "https://www.youtube.com/watch?v=jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://m.youtube.com/watch?v=jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            ("youtube.com/watch?v=jfNfCLB8Bm4", Some("jfNfCLB8Bm4")),
+            (
+                "https://www.youtube.com/watch?feature=share&v=jfNfCLB8Bm4&t=42s",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://www.youtube.com/live_chat?is_popout=1&v=jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            ("https://youtu.be/jfNfCLB8Bm4", Some("jfNfCLB8Bm4")),
+            (
+                "https://youtu.be/jfNfCLB8Bm4?si=abcdef&t=10",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://www.youtube.com/live/jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://www.youtube.com/live/jfNfCLB8Bm4?feature=share",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://www.youtube.com/shorts/jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            (
+                "https://www.youtube.com/embed/jfNfCLB8Bm4",
+                Some("jfNfCLB8Bm4"),
+            ),
+            ("", None),
+            ("jfNfCLB8Bm", None),
+            ("jfNfCLB8Bm4x", None),
+            ("jfNfCLB8Bm!", None),
+            ("https://www.youtube.com/watch?list=PL123", None),
+            ("https://www.youtube.com/watch?v=tooshort", None),
+            ("https://www.youtube.com/@channel", None),
+            ("https://www.youtube.com/live/", None),
+            ("https://youtu.be/", None),
+            ("https://example.com/watch?v=jfNfCLB8Bm4", None),
+            ("not a url", None),
+        ];
+        for (video, expected) in cases {
+            assert_eq!(parse_video_id(video).as_deref(), expected, "{}", video);
+        }
+    }
+}
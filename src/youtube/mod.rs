@@ -1,2 +1,3 @@
 pub mod misc;
+pub mod models;
 pub mod scraper;
@@ -0,0 +1,545 @@
+//! Typed models of the innertube `get_live_chat` and `get_live_chat_replay` responses,
+//! only the parts that are used are modeled, everything else is ignored.
+//!
+//! Youtube wraps most objects in a single key naming their type, like `{"addChatItemAction": {..}}`,
+//! these are enums where the `Unknown` variant catches every type that isn't modeled,
+//! and also a known type whose shape changed, so a schema change drops a message instead of the chat.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetLiveChatResponse {
+    pub continuation_contents: Option<ContinuationContents>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContinuationContents {
+    pub live_chat_continuation: Option<LiveChatContinuation>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveChatContinuation {
+    #[serde(default)]
+    pub continuations: Vec<ContinuationItem>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContinuationItem {
+    InvalidationContinuationData(ContinuationData),
+    TimedContinuationData(ContinuationData),
+    ReloadContinuationData(ContinuationData),
+    LiveChatReplayContinuationData(ContinuationData),
+    /// for example `playerSeekContinuationData` at the end of a replay
+    #[serde(untagged)]
+    Unknown(Value),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContinuationData {
+    pub continuation: String,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Action {
+    AddChatItemAction {
+        item: ChatItem,
+    },
+    MarkChatItemAsDeletedAction {
+        target_item_id: String,
+    },
+    MarkChatItemsByAuthorAsDeletedAction {
+        external_channel_id: String,
+    },
+    ReplaceChatItemAction {
+        target_item_id: String,
+        replacement_item: ChatItem,
+    },
+    AddBannerToLiveChatCommand {
+        banner_renderer: BannerRenderer,
+    },
+    /// the actions of a replay chat are wrapped with the offset into the stream they were shown at
+    ReplayChatItemAction {
+        #[serde(default)]
+        actions: Vec<Action>,
+        video_offset_time_msec: Option<String>,
+    },
+    #[serde(untagged)]
+    Unknown(Value),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BannerRenderer {
+    pub live_chat_banner_renderer: LiveChatBanner,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveChatBanner {
+    /// the pinned message, a chat item without a timestamp
+    pub contents: ChatItem,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChatItem {
+    LiveChatTextMessageRenderer(TextMessage),
+    LiveChatPaidMessageRenderer(PaidMessage),
+    LiveChatPaidStickerRenderer(PaidSticker),
+    LiveChatMembershipItemRenderer(MembershipItem),
+    LiveChatSponsorshipsGiftPurchaseAnnouncementRenderer(GiftPurchase),
+    /// placeholders, viewer engagement messages and such
+    #[serde(untagged)]
+    Unknown(Value),
+}
+
+impl ChatItem {
+    pub fn base(&self) -> Option<&ChatItemBase> {
+        match self {
+            ChatItem::LiveChatTextMessageRenderer(renderer) => Some(&renderer.base),
+            ChatItem::LiveChatPaidMessageRenderer(renderer) => Some(&renderer.base),
+            ChatItem::LiveChatPaidStickerRenderer(renderer) => Some(&renderer.base),
+            ChatItem::LiveChatMembershipItemRenderer(renderer) => Some(&renderer.base),
+            ChatItem::LiveChatSponsorshipsGiftPurchaseAnnouncementRenderer(renderer) => {
+                Some(&renderer.base)
+            }
+            ChatItem::Unknown(_) => None,
+        }
+    }
+}
+
+/// The fields every chat item renderer has
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatItemBase {
+    pub id: Option<String>,
+    /// microseconds since the unix epoch, as a string
+    pub timestamp_usec: Option<String>,
+    pub author_name: Option<Text>,
+    pub author_external_channel_id: Option<String>,
+    #[serde(default)]
+    pub author_badges: Vec<AuthorBadge>,
+    pub author_photo: Option<Thumbnails>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextMessage {
+    #[serde(flatten)]
+    pub base: ChatItemBase,
+    pub message: Option<Text>,
+}
+
+/// A super chat
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaidMessage {
+    #[serde(flatten)]
+    pub base: ChatItemBase,
+    pub purchase_amount_text: Option<Text>,
+    pub message: Option<Text>,
+    /// colors are `0xAARRGGBB`
+    pub header_background_color: Option<u32>,
+    pub body_background_color: Option<u32>,
+}
+
+/// A super sticker
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaidSticker {
+    #[serde(flatten)]
+    pub base: ChatItemBase,
+    pub purchase_amount_text: Option<Text>,
+    pub sticker: Option<Sticker>,
+    pub money_chip_background_color: Option<u32>,
+    pub background_color: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sticker {
+    pub accessibility: Option<Accessibility>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Accessibility {
+    pub accessibility_data: AccessibilityData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessibilityData {
+    pub label: String,
+}
+
+/// A new member or a membership milestone
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipItem {
+    #[serde(flatten)]
+    pub base: ChatItemBase,
+    pub header_primary_text: Option<Text>,
+    pub header_subtext: Option<Text>,
+    pub message: Option<Text>,
+}
+
+/// Gifted memberships, the author is in the header
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GiftPurchase {
+    #[serde(flatten)]
+    pub base: ChatItemBase,
+    pub header: GiftPurchaseHeader,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GiftPurchaseHeader {
+    pub live_chat_sponsorships_header_renderer: SponsorshipsHeader,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SponsorshipsHeader {
+    pub author_name: Option<Text>,
+    /// "Gifted 5 {channel} memberships"
+    pub primary_text: Option<Text>,
+    #[serde(default)]
+    pub author_badges: Vec<AuthorBadge>,
+}
+
+/// A youtube text object, which is either `{"simpleText": ""}` or `{"runs": [..]}` of text and emoji runs
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Text {
+    pub simple_text: Option<String>,
+    #[serde(default)]
+    pub runs: Vec<Run>,
+}
+
+impl Text {
+    /// the text with custom emojis as their first shortcut, like `:yt:`, and other emojis as themselves
+    pub fn plain_text(&self) -> String {
+        if let Some(simple_text) = &self.simple_text {
+            return simple_text.clone();
+        }
+        self.runs
+            .iter()
+            .map(|run| match &run.emoji {
+                Some(emoji) if emoji.is_custom_emoji => {
+                    emoji.shortcuts.first().cloned().unwrap_or_default()
+                }
+                Some(emoji) => emoji.emoji_id.clone().unwrap_or_default(),
+                None => run.text.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub text: Option<String>,
+    pub emoji: Option<Emoji>,
+    /// set on text runs that are links
+    pub navigation_endpoint: Option<NavigationEndpoint>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NavigationEndpoint {
+    pub url_endpoint: Option<UrlEndpoint>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlEndpoint {
+    /// a `https://www.youtube.com/redirect?..` url for links to other sites
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Emoji {
+    /// the emoji itself for unicode emojis, an opaque id for custom emojis
+    pub emoji_id: Option<String>,
+    #[serde(default)]
+    pub shortcuts: Vec<String>,
+    #[serde(default)]
+    pub is_custom_emoji: bool,
+    pub image: Option<Thumbnails>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorBadge {
+    pub live_chat_author_badge_renderer: AuthorBadgeRenderer,
+}
+
+/// Builtin badges have an icon, member badges have a custom thumbnail
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorBadgeRenderer {
+    /// for example: "Moderator" or "Member (6 months)"
+    pub tooltip: Option<String>,
+    pub icon: Option<Icon>,
+    pub custom_thumbnail: Option<Thumbnails>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Icon {
+    pub icon_type: IconType,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum IconType {
+    Owner,
+    Moderator,
+    /// the check mark of verified channels
+    #[serde(alias = "CHECK_CIRCLE_THICK")]
+    Verified,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnails {
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl Thumbnails {
+    /// the thumbnails are listed from small to large
+    pub fn largest_url(&self) -> Option<&str> {
+        self.thumbnails
+            .last()
+            .map(|thumbnail| thumbnail.url.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thumbnail {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_LIVE_CHAT: &str = include_str!("../../tests/fixtures/get_live_chat.json");
+    const GET_LIVE_CHAT_REPLAY: &str =
+        include_str!("../../tests/fixtures/get_live_chat_replay.json");
+
+    fn live_chat_continuation(response: &str) -> LiveChatContinuation {
+        let response: GetLiveChatResponse = serde_json::from_str(response).unwrap();
+        response
+            .continuation_contents
+            .unwrap()
+            .live_chat_continuation
+            .unwrap()
+    }
+
+    fn added_item(action: &Action) -> &ChatItem {
+        match action {
+            Action::AddChatItemAction { item } => item,
+            other => panic!("expected addChatItemAction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_live_chat() {
+        let chat = live_chat_continuation(GET_LIVE_CHAT);
+
+        assert!(matches!(
+            &chat.continuations[..],
+            [ContinuationItem::InvalidationContinuationData(
+                ContinuationData {
+                    timeout_ms: Some(10000),
+                    ..
+                }
+            )]
+        ));
+        assert_eq!(chat.actions.len(), 8);
+
+        let ChatItem::LiveChatTextMessageRenderer(text) = added_item(&chat.actions[0]) else {
+            panic!("expected a text message, got {:?}", chat.actions[0]);
+        };
+        assert_eq!(
+            text.message.as_ref().unwrap().plain_text(),
+            "hello chat :yt: 😀 see example.com"
+        );
+        assert_eq!(
+            text.base.author_photo.as_ref().unwrap().largest_url(),
+            Some("https://yt4.ggpht.com/photo-64")
+        );
+        let badges: Vec<_> = text
+            .base
+            .author_badges
+            .iter()
+            .map(|badge| &badge.live_chat_author_badge_renderer)
+            .collect();
+        assert_eq!(
+            badges[0].icon.as_ref().unwrap().icon_type,
+            IconType::Moderator
+        );
+        assert!(badges[1].icon.is_none());
+        assert_eq!(badges[1].tooltip.as_deref(), Some("Member (6 months)"));
+        assert_eq!(
+            badges[2].icon.as_ref().unwrap().icon_type,
+            IconType::Verified
+        );
+        // badge icons that aren't modeled fall back to `IconType::Unknown`
+        let icon: Icon = serde_json::from_str(r#"{"iconType": "YOUTUBE_ROUND"}"#).unwrap();
+        assert_eq!(icon.icon_type, IconType::Unknown);
+
+        let ChatItem::LiveChatPaidMessageRenderer(paid) = added_item(&chat.actions[1]) else {
+            panic!("expected a super chat, got {:?}", chat.actions[1]);
+        };
+        assert_eq!(
+            paid.purchase_amount_text.as_ref().unwrap().plain_text(),
+            "$20.00"
+        );
+        assert_eq!(paid.header_background_color, Some(4278239141));
+        assert_eq!(paid.body_background_color, Some(4280150454));
+
+        assert!(matches!(
+            added_item(&chat.actions[2]),
+            ChatItem::LiveChatMembershipItemRenderer(_)
+        ));
+        let ChatItem::LiveChatSponsorshipsGiftPurchaseAnnouncementRenderer(gift) =
+            added_item(&chat.actions[3])
+        else {
+            panic!("expected gifted memberships, got {:?}", chat.actions[3]);
+        };
+        let header = &gift.header.live_chat_sponsorships_header_renderer;
+        assert_eq!(
+            header.primary_text.as_ref().unwrap().plain_text(),
+            "Gifted 5 Some Channel memberships"
+        );
+
+        // renderers that aren't modeled are kept as they are
+        let viewer_engagement = added_item(&chat.actions[4]);
+        assert!(viewer_engagement.base().is_none());
+        let ChatItem::Unknown(value) = viewer_engagement else {
+            panic!("expected an unknown item, got {:?}", viewer_engagement);
+        };
+        assert!(value
+            .get("liveChatViewerEngagementMessageRenderer")
+            .is_some());
+
+        assert!(matches!(
+            &chat.actions[5],
+            Action::MarkChatItemAsDeletedAction { target_item_id }
+                if target_item_id == "ChwKGkNNaXk1ZU9PbTRJREZRVVEtd29kWXN3TmRR"
+        ));
+        assert!(matches!(
+            &chat.actions[6],
+            Action::AddBannerToLiveChatCommand { banner_renderer }
+                if matches!(
+                    banner_renderer.live_chat_banner_renderer.contents,
+                    ChatItem::LiveChatTextMessageRenderer(_)
+                )
+        ));
+        let Action::Unknown(value) = &chat.actions[7] else {
+            panic!("expected an unknown action, got {:?}", chat.actions[7]);
+        };
+        assert!(value.get("addLiveChatTickerItemAction").is_some());
+    }
+
+    #[test]
+    fn get_live_chat_replay() {
+        let chat = live_chat_continuation(GET_LIVE_CHAT_REPLAY);
+
+        let [ContinuationItem::LiveChatReplayContinuationData(next), ContinuationItem::Unknown(player_seek)] =
+            &chat.continuations[..]
+        else {
+            panic!("unexpected continuations {:?}", chat.continuations);
+        };
+        assert!(next.continuation.starts_with("op2w0wR2"));
+        assert!(player_seek.get("playerSeekContinuationData").is_some());
+
+        let offsets: Vec<_> = chat
+            .actions
+            .iter()
+            .map(|action| match action {
+                Action::ReplayChatItemAction {
+                    actions,
+                    video_offset_time_msec,
+                } => {
+                    assert_eq!(actions.len(), 1);
+                    video_offset_time_msec.as_deref().unwrap()
+                }
+                other => panic!("expected replayChatItemAction, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(offsets, ["3021", "15012", "20000", "21500"]);
+
+        let replayed = |i: usize| match &chat.actions[i] {
+            Action::ReplayChatItemAction { actions, .. } => &actions[0],
+            _ => unreachable!(),
+        };
+        assert!(matches!(
+            added_item(replayed(0)),
+            ChatItem::LiveChatTextMessageRenderer(_)
+        ));
+        let ChatItem::LiveChatPaidStickerRenderer(sticker) = added_item(replayed(1)) else {
+            panic!("expected a super sticker, got {:?}", replayed(1));
+        };
+        assert_eq!(
+            sticker
+                .sticker
+                .as_ref()
+                .unwrap()
+                .accessibility
+                .as_ref()
+                .unwrap()
+                .accessibility_data
+                .label,
+            "A cat waving hello"
+        );
+        assert!(matches!(added_item(replayed(2)), ChatItem::Unknown(_)));
+        assert!(matches!(
+            replayed(3),
+            Action::MarkChatItemsByAuthorAsDeletedAction { external_channel_id }
+                if external_channel_id == "UCbbbbbbbbbbbbbbbbbbbbbb"
+        ));
+    }
+
+    #[test]
+    fn changed_renderer_is_unknown() {
+        // a known renderer whose shape changed drops the message instead of the whole response
+        let chat = live_chat_continuation(
+            r#"{"continuationContents": {"liveChatContinuation": {"actions": [
+                {"addChatItemAction": {"item": {"liveChatTextMessageRenderer": {"message": 5}}}},
+                {"markChatItemAsDeletedAction": {"targetItemId": ["not", "a", "string"]}}
+            ]}}}"#,
+        );
+        assert!(matches!(added_item(&chat.actions[0]), ChatItem::Unknown(_)));
+        assert!(matches!(chat.actions[1], Action::Unknown(_)));
+        assert!(chat.continuations.is_empty());
+    }
+
+    #[test]
+    fn without_continuation_contents() {
+        let response: GetLiveChatResponse =
+            serde_json::from_str(r#"{"responseContext": {}, "trackingParams": "CAAQ"}"#).unwrap();
+        assert!(response.continuation_contents.is_none());
+    }
+}
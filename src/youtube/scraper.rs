@@ -1,185 +1,850 @@
-use crate::{types::ChatMsg, youtube::misc::get_ytcfg};
+use crate::config::YoutubeTarget;
+use crate::source::Health;
+use crate::supervisor::{supervise, Backoff, SourceError};
+use crate::types::{
+    Author, Badge, ChatEvent, ChatLocation, ChatMsg, ChatNotice, Fragment, LiveState, NoticeKind,
+    Role,
+};
+use crate::youtube::misc::{
+    build_client, get_ytcfg, get_ytcfg_for_replay, get_ytcfg_for_video, parse_video_id, YtCfg,
+};
+use crate::youtube::models::{
+    Action, ChatItem, ChatItemBase, ContinuationData, ContinuationItem, GetLiveChatResponse,
+    IconType, Text, Thumbnails,
+};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use jsonpath_rust::JsonPathQuery;
-
-use log::warn;
+use log::{debug, error, info};
 use reqwest::header::{self, HeaderMap};
 use serde::Serialize;
 use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::Sender;
 use tokio::time::sleep;
 
-pub async fn main(tx: Sender<ChatMsg>, youtube_channel_name: &str) {
-    let ytcfg = get_ytcfg(youtube_channel_name).await.unwrap();
+pub async fn main(
+    tx: Sender<ChatEvent>,
+    target: YoutubeTarget,
+    proxy: Option<String>,
+    health: Health,
+) {
+    let name = format!("youtube {}", target);
+    // one client for the whole source, so connections and cookies carry over between polls
+    let client = match build_client(proxy.as_deref()) {
+        Ok(client) => client,
+        Err(err) => {
+            error!("{} can't start, {}", name, err);
+            health.finished();
+            return;
+        }
+    };
+    let replay_cursor = ReplayCursor::default();
+    supervise(&name, &health, Backoff::default(), || {
+        follow(
+            tx.clone(),
+            client.clone(),
+            target.clone(),
+            replay_cursor.clone(),
+        )
+    })
+    .await
+}
+
+/// how often a channel that isn't live is checked
+const WAITING_POLL_INTERVAL: Duration = Duration::from_secs(60);
 
+/// follow() follows a channel from stream to stream forever,
+/// or the chat of a single video until its stream ends
+async fn follow(
+    tx: Sender<ChatEvent>,
+    client: reqwest::Client,
+    target: YoutubeTarget,
+    replay_cursor: ReplayCursor,
+) -> Result<(), SourceError> {
+    match target {
+        YoutubeTarget::Channel(channel_name) => {
+            let mut ended_video_id = None;
+            loop {
+                let ytcfg =
+                    wait_for_live(&tx, &client, &channel_name, ended_video_id.as_deref()).await?;
+                info!(
+                    "youtube channel {} is live, {}",
+                    channel_name, ytcfg.video_id
+                );
+                let video_id = ytcfg.video_id.clone();
+                send_live_state(
+                    &tx,
+                    &channel_name,
+                    LiveState::Live {
+                        video_id: video_id.clone(),
+                    },
+                )
+                .await?;
+
+                poll_chat(&tx, &client, &ytcfg, &channel_name).await?;
+                send_live_state(
+                    &tx,
+                    &channel_name,
+                    LiveState::Ended {
+                        video_id: video_id.clone(),
+                    },
+                )
+                .await?;
+                ended_video_id = Some(video_id);
+            }
+        }
+        YoutubeTarget::Video(video) => {
+            let video_id = parse_video_id(&video).ok_or(SourceError::Other(format!(
+                "invalid youtube video {}",
+                video
+            )))?;
+            let ytcfg = get_ytcfg_for_video(&client, &video_id)
+                .await?
+                .ok_or(SourceError::Other(format!("{} has no live chat", video_id)))?;
+
+            // messages of a video are tagged with the video id instead of a channel name
+            poll_chat(&tx, &client, &ytcfg, &video_id).await?;
+            send_live_state(
+                &tx,
+                &video_id,
+                LiveState::Ended {
+                    video_id: video_id.clone(),
+                },
+            )
+            .await?;
+            Ok(())
+        }
+        YoutubeTarget::Replay(video) => {
+            let video_id = parse_video_id(&video).ok_or(SourceError::Other(format!(
+                "invalid youtube video {}",
+                video
+            )))?;
+            let ytcfg =
+                get_ytcfg_for_replay(&client, &video_id)
+                    .await?
+                    .ok_or(SourceError::Other(format!(
+                        "{} has no chat replay",
+                        video_id
+                    )))?;
+
+            fetch_replay(&tx, &client, &ytcfg, &video_id, &replay_cursor).await
+        }
+    }
+}
+
+/// wait_for_live() checks the `/live` page of a channel until it has a live chat,
+/// for a while after a stream ends the page still serves it, so `ended_video_id` doesn't count as live
+async fn wait_for_live(
+    tx: &Sender<ChatEvent>,
+    client: &reqwest::Client,
+    channel_name: &str,
+    ended_video_id: Option<&str>,
+) -> Result<YtCfg, SourceError> {
+    let mut waiting = false;
+    loop {
+        if let Some(ytcfg) = get_ytcfg(client, channel_name).await? {
+            if Some(ytcfg.video_id.as_str()) != ended_video_id {
+                return Ok(ytcfg);
+            }
+        }
+        if !waiting {
+            info!("youtube channel {} isn't live, waiting", channel_name);
+            send_live_state(tx, channel_name, LiveState::Waiting).await?;
+            waiting = true;
+        }
+        sleep(WAITING_POLL_INTERVAL).await;
+    }
+}
+
+async fn send_live_state(
+    tx: &Sender<ChatEvent>,
+    channel: &str,
+    state: LiveState,
+) -> Result<(), SourceError> {
+    let chat_event = ChatNotice {
+        location: ChatLocation::YouTube,
+        channel: channel.to_string(),
+        timestamp: Utc::now(),
+        stream_offset: None,
+        kind: NoticeKind::LiveState { state },
+        raw_full_msg: String::new(),
+    };
+    tx.send(chat_event.into()).await?;
+    Ok(())
+}
+
+/// poll_chat() polls the chat of a stream until it ends
+async fn poll_chat(
+    tx: &Sender<ChatEvent>,
+    client: &reqwest::Client,
+    ytcfg: &YtCfg,
+    youtube_channel_name: &str,
+) -> Result<(), SourceError> {
     let url = format!(
         "https://www.youtube.com/youtubei/v1/live_chat/get_live_chat?key={}&prettyPrint=false",
         ytcfg.chat_id
     );
 
     let mut continuation = ytcfg.first_continuation.clone();
+    let mut author_msgs = AuthorMsgs::default();
 
     loop {
-        let headers = get_headers();
-        let body = serde_json::to_string(&GetLiveMsgReq::new(continuation)).unwrap();
-        let res = reqwest::Client::new()
-            .post(&url)
-            .headers(headers)
-            .body(body)
-            .send()
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
+        let res = post_continuation(client, &url, ytcfg, continuation).await?;
+
+        let (next, timeout) =
+            match parse_yt_msg(tx, youtube_channel_name, &mut author_msgs, res).await? {
+                Continuation::Timed { token, timeout } => (token, timeout),
+                Continuation::Reload { token } => {
+                    debug!("youtube asked to reload the chat");
+                    (token, MIN_POLL_INTERVAL)
+                }
+                Continuation::Ended => {
+                    info!("youtube chat of {} ended", youtube_channel_name);
+                    return Ok(());
+                }
+            };
+        continuation = next;
 
-        continuation = parse_yt_msg(&tx, res);
+        sleep(timeout).await;
+    }
+}
 
-        sleep(Duration::from_secs(4)).await;
+/// how long to wait between the pages of a replay chat, which youtube serves as fast as asked
+const REPLAY_PAGE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The continuation of the next replay page, kept across reconnects
+/// so a replay resumes after the last page it sent instead of sending every message again
+#[derive(Debug, Clone, Default)]
+struct ReplayCursor(Arc<Mutex<Option<String>>>);
+
+impl ReplayCursor {
+    fn get(&self) -> Option<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn set(&self, continuation: String) {
+        *self.0.lock().unwrap() = Some(continuation);
     }
 }
 
-fn parse_yt_msg(tx: &Sender<ChatMsg>, raw_message: String) -> String {
-    let message_as_val: Value = serde_json::from_str(&raw_message).unwrap();
-    let continuation = message_as_val.clone().path("$.continuationContents.liveChatContinuation.continuations[0].invalidationContinuationData.continuation").unwrap();
-    let actions = message_as_val
-        .path("$.continuationContents.liveChatContinuation.actions[*]")
-        .unwrap();
+/// fetch_replay() fetches the whole replay chat of a finished stream, page by page,
+/// starting after the last page sent before a reconnect
+async fn fetch_replay(
+    tx: &Sender<ChatEvent>,
+    client: &reqwest::Client,
+    ytcfg: &YtCfg,
+    video_id: &str,
+    replay_cursor: &ReplayCursor,
+) -> Result<(), SourceError> {
+    let url = format!(
+        "https://www.youtube.com/youtubei/v1/live_chat/get_live_chat_replay?key={}&prettyPrint=false",
+        ytcfg.chat_id
+    );
+
+    let mut continuation = replay_cursor
+        .get()
+        .unwrap_or_else(|| ytcfg.first_continuation.clone());
+    let mut author_msgs = AuthorMsgs::default();
 
-    for action in actions.as_array().unwrap() {
-        let first_key = action.as_object().unwrap().keys().nth(0).unwrap();
-        if first_key != "addChatItemAction" {
+    loop {
+        let res = post_continuation(client, &url, ytcfg, continuation).await?;
+
+        let Some(next) = parse_replay_msg(tx, video_id, &mut author_msgs, res).await? else {
+            info!("youtube replay chat of {} fetched", video_id);
+            return Ok(());
+        };
+        replay_cursor.set(next.clone());
+        continuation = next;
+
+        sleep(REPLAY_PAGE_INTERVAL).await;
+    }
+}
+
+/// parse_replay_msg() sends the chat events of a `get_live_chat_replay` response,
+/// and returns the continuation of the next page, `None` after the last page
+async fn parse_replay_msg(
+    tx: &Sender<ChatEvent>,
+    channel: &str,
+    author_msgs: &mut AuthorMsgs,
+    raw_message: String,
+) -> Result<Option<String>, SourceError> {
+    let response: GetLiveChatResponse = serde_json::from_str(&raw_message).map_err(|err| {
+        SourceError::Other(format!("invalid get_live_chat_replay response, {}", err))
+    })?;
+    // an error payload mid replay must not pass for its end, only a missing continuation does
+    let live_chat_continuation = response
+        .continuation_contents
+        .and_then(|v| v.live_chat_continuation)
+        .ok_or(SourceError::Other(
+            "get_live_chat_replay response without continuationContents".to_string(),
+        ))?;
+
+    for action in live_chat_continuation.actions {
+        // every action is wrapped with the offset into the stream it was shown at
+        let Action::ReplayChatItemAction {
+            actions,
+            video_offset_time_msec,
+        } = action
+        else {
             continue;
+        };
+        let stream_offset = video_offset_time_msec
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis);
+
+        for action in &actions {
+            for mut chat_event in parse_action(action, channel, author_msgs) {
+                match &mut chat_event {
+                    ChatEvent::Msg(msg) => msg.stream_offset = stream_offset,
+                    ChatEvent::Notice(notice) => notice.stream_offset = stream_offset,
+                }
+                tx.send(chat_event).await?;
+            }
         }
-        let item = action
-            .get("addChatItemAction")
-            .and_then(|v| v.get("item"))
-            .unwrap();
-        let item_first_key = item.as_object().unwrap().keys().nth(0).unwrap();
+    }
 
-        if item_first_key != "liveChatTextMessageRenderer" {
-            continue;
+    let next = live_chat_continuation
+        .continuations
+        .into_iter()
+        .find_map(|continuation| match continuation {
+            ContinuationItem::LiveChatReplayContinuationData(data) => Some(data.continuation),
+            _ => None,
+        });
+    Ok(next)
+}
+
+/// post_continuation() asks an innertube endpoint for the page after `continuation`
+async fn post_continuation(
+    client: &reqwest::Client,
+    url: &str,
+    ytcfg: &YtCfg,
+    continuation: String,
+) -> Result<String, SourceError> {
+    let headers = get_headers(ytcfg);
+    let body = serde_json::to_string(&GetLiveMsgReq::new(ytcfg, continuation)).unwrap();
+    let res = client
+        .post(url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Ok(res)
+}
+
+/// how long to wait between polls when youtube doesn't say
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(4);
+/// never poll more often than this, whatever youtube says
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// never poll less often than this, so a bogus timeout can't stall the chat
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What to do after a `get_live_chat` response
+#[derive(Debug, PartialEq)]
+enum Continuation {
+    /// poll again with `token` after `timeout`,
+    /// from `invalidationContinuationData` or `timedContinuationData`
+    Timed { token: String, timeout: Duration },
+    /// poll again with `token` after `MIN_POLL_INTERVAL`, from `reloadContinuationData`
+    Reload { token: String },
+    /// there is no next continuation, the stream is over
+    Ended,
+}
+
+impl Continuation {
+    /// parse() reads the first continuation of a `liveChatContinuation`
+    fn parse(continuations: Vec<ContinuationItem>) -> Self {
+        let timeout = |data: &ContinuationData| {
+            data.timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_POLL_INTERVAL)
+                .clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+        };
+
+        match continuations.into_iter().next() {
+            Some(
+                ContinuationItem::InvalidationContinuationData(data)
+                | ContinuationItem::TimedContinuationData(data),
+            ) => Continuation::Timed {
+                timeout: timeout(&data),
+                token: data.continuation,
+            },
+            Some(ContinuationItem::ReloadContinuationData(data)) => Continuation::Reload {
+                token: data.continuation,
+            },
+            _ => Continuation::Ended,
         }
+    }
+}
 
-        let raw_full_msg = serde_json::to_string(&item).unwrap();
+async fn parse_yt_msg(
+    tx: &Sender<ChatEvent>,
+    channel: &str,
+    author_msgs: &mut AuthorMsgs,
+    raw_message: String,
+) -> Result<Continuation, SourceError> {
+    let response: GetLiveChatResponse = serde_json::from_str(&raw_message)
+        .map_err(|err| SourceError::Other(format!("invalid get_live_chat response, {}", err)))?;
+    // error payloads and consent pages have no chat at all, only a missing continuation ends the stream
+    let live_chat_continuation = response
+        .continuation_contents
+        .and_then(|v| v.live_chat_continuation)
+        .ok_or(SourceError::Other(
+            "get_live_chat response without continuationContents".to_string(),
+        ))?;
 
-        let message_runs = item
-            .get("liveChatTextMessageRenderer")
-            .and_then(|v| v.get("message"))
-            .and_then(|v| v.get("runs"))
-            .unwrap()
-            .as_array()
-            .unwrap();
-
-        let mut message = String::from("");
-
-        for message_run in message_runs {
-            if message_run.get("emoji").is_some() {
-                let is_custom_emoji = message_run
-                    .get("emoji")
-                    .and_then(|v| v.get("isCustomEmoji"))
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(false);
-                if is_custom_emoji {
-                    let emoji_id = message_run
-                        .get("emoji")
-                        .and_then(|v| v.get("shortcuts"))
-                        .and_then(|v| v.get(0))
-                        .unwrap()
-                        .as_str()
-                        .unwrap();
-                    message.push_str(emoji_id);
-                } else {
-                    let emoji = message_run
-                        .get("emoji")
-                        .and_then(|v| v.get("emojiId"))
-                        .unwrap()
-                        .as_str()
-                        .unwrap();
-                    message.push_str(emoji);
+    for action in &live_chat_continuation.actions {
+        for chat_event in parse_action(action, channel, author_msgs) {
+            tx.send(chat_event).await?;
+        }
+    }
+
+    Ok(Continuation::parse(live_chat_continuation.continuations))
+}
+
+/// parse_action() returns the chat events of a single live chat action
+fn parse_action(action: &Action, channel: &str, author_msgs: &mut AuthorMsgs) -> Vec<ChatEvent> {
+    let notice = |kind: NoticeKind| -> ChatEvent {
+        ChatNotice {
+            location: ChatLocation::YouTube,
+            channel: channel.to_string(),
+            timestamp: Utc::now(),
+            stream_offset: None,
+            kind,
+            raw_full_msg: serde_json::to_string(action).unwrap(),
+        }
+        .into()
+    };
+
+    match action {
+        Action::AddChatItemAction { item } => {
+            let Some(chat_event) = parse_chat_item(item, channel) else {
+                return vec![];
+            };
+            if let (ChatEvent::Msg(msg), Some(author_id)) = (&chat_event, get_author_id(item)) {
+                author_msgs.insert(author_id.to_string(), msg);
+            }
+            vec![chat_event]
+        }
+        Action::MarkChatItemAsDeletedAction { target_item_id } => {
+            let kind = NoticeKind::MsgDeleted {
+                msg_id: target_item_id.clone(),
+            };
+            vec![notice(kind)]
+        }
+        Action::MarkChatItemsByAuthorAsDeletedAction {
+            external_channel_id,
+        } => {
+            let (author, msg_ids) = author_msgs
+                .remove(external_channel_id)
+                .unwrap_or((external_channel_id.clone(), vec![]));
+            let mut chat_events: Vec<ChatEvent> = msg_ids
+                .into_iter()
+                .map(|msg_id| notice(NoticeKind::MsgDeleted { msg_id }))
+                .collect();
+            chat_events.push(notice(NoticeKind::AuthorRemoved { author }));
+            chat_events
+        }
+        Action::ReplaceChatItemAction {
+            target_item_id,
+            replacement_item,
+        } => match parse_chat_item(replacement_item, channel) {
+            Some(ChatEvent::Msg(msg)) => {
+                if let Some(author_id) = get_author_id(replacement_item) {
+                    author_msgs.forget(author_id, target_item_id);
+                    author_msgs.insert(author_id.to_string(), &msg);
                 }
-            } else if message_run.get("text").is_some() {
-                let text = message_run.get("text").unwrap().as_str().unwrap();
-                message.push_str(text);
+                let kind = NoticeKind::MsgReplaced {
+                    msg_id: target_item_id.clone(),
+                    msg: Box::new(msg),
+                };
+                vec![notice(kind)]
             }
+            // placeholders can be replaced by super chats and such, those are shown as new
+            Some(chat_event) => vec![chat_event],
+            None => vec![],
+        },
+        Action::AddBannerToLiveChatCommand { banner_renderer } => {
+            let ChatItem::LiveChatTextMessageRenderer(pinned) =
+                &banner_renderer.live_chat_banner_renderer.contents
+            else {
+                return vec![];
+            };
+            let kind = NoticeKind::MsgPinned {
+                author: get_text(&pinned.base.author_name).unwrap_or_default(),
+                msg_text: get_text(&pinned.message).unwrap_or_default(),
+            };
+            vec![notice(kind)]
+        }
+        Action::ReplayChatItemAction { .. } => vec![],
+        Action::Unknown(raw) => {
+            debug!("unknown youtube action {}", raw);
+            vec![]
         }
+    }
+}
 
-        let timestamp_usec: i64 = item
-            .get("liveChatTextMessageRenderer")
-            .and_then(|v| v.get("timestampUsec"))
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .parse()
-            .unwrap();
+/// get_author_id() returns the channel id of the author of a chat item
+fn get_author_id(item: &ChatItem) -> Option<&str> {
+    item.base()?.author_external_channel_id.as_deref()
+}
 
-        let timestamp = NaiveDateTime::from_timestamp_micros(timestamp_usec).unwrap();
+/// how many authors, and messages of each author, are remembered so they can be removed later
+const AUTHOR_MSGS_CAPACITY: usize = 1000;
 
-        let dt: DateTime<Utc> = DateTime::from_naive_utc_and_offset(timestamp, Utc);
+/// The ids of the recent messages of each author, keyed by the author channel id,
+/// youtube removes all messages of an author by their channel id only
+#[derive(Debug, Default)]
+struct AuthorMsgs {
+    authors: HashMap<String, (String, Vec<String>)>,
+    order: VecDeque<String>,
+}
 
-        let author = item
-            .get("liveChatTextMessageRenderer")
-            .and_then(|v| v.get("authorName"))
-            .and_then(|v| v.get("simpleText"))
-            .unwrap()
-            .as_str()
-            .unwrap();
+impl AuthorMsgs {
+    fn insert(&mut self, author_id: String, msg: &ChatMsg) {
+        let Some(msg_id) = msg.id.clone() else {
+            return;
+        };
+        if !self.authors.contains_key(&author_id) {
+            if self.order.len() >= AUTHOR_MSGS_CAPACITY {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.authors.remove(&oldest);
+                }
+            }
+            self.order.push_back(author_id.clone());
+        }
+        let (author, msg_ids) = self
+            .authors
+            .entry(author_id)
+            .or_insert((msg.author.name.clone(), vec![]));
+        author.clone_from(&msg.author.name);
+        if msg_ids.len() >= AUTHOR_MSGS_CAPACITY {
+            msg_ids.remove(0);
+        }
+        msg_ids.push(msg_id);
+    }
+
+    /// forget() drops a message id of an author, for example once it was replaced
+    fn forget(&mut self, author_id: &str, msg_id: &str) {
+        if let Some((_, msg_ids)) = self.authors.get_mut(author_id) {
+            msg_ids.retain(|id| id != msg_id);
+        }
+    }
+
+    /// remove() returns the name and message ids of an author
+    fn remove(&mut self, author_id: &str) -> Option<(String, Vec<String>)> {
+        self.order.retain(|id| id != author_id);
+        self.authors.remove(author_id)
+    }
+}
+
+/// parse_chat_item() turns the item of an `addChatItemAction` into a chat event,
+/// `None` for renderers that aren't shown (like placeholders and viewer engagement messages)
+fn parse_chat_item(item: &ChatItem, channel: &str) -> Option<ChatEvent> {
+    let base = item.base()?;
+    let raw_full_msg = serde_json::to_string(&item).unwrap();
+    let timestamp = get_timestamp(base)?;
+    let author = get_author(base);
+
+    let kind = match item {
+        ChatItem::LiveChatTextMessageRenderer(renderer) => {
+            let message = get_text(&renderer.message).unwrap_or_default();
+
+            return Some(
+                ChatMsg {
+                    id: base.id.clone(),
+                    author,
+                    location: ChatLocation::YouTube,
+                    channel: channel.to_string(),
+                    fragments: get_fragments(&renderer.message),
+                    msg_text: message,
+                    raw_full_msg,
+                    timestamp,
+                    stream_offset: None,
+                }
+                .into(),
+            );
+        }
+        ChatItem::LiveChatPaidMessageRenderer(renderer) => {
+            let amount_text = get_text(&renderer.purchase_amount_text).unwrap_or_default();
+            let (amount, currency) = parse_amount(&amount_text);
+            NoticeKind::Paid {
+                msg_id: base.id.clone(),
+                author: author.name,
+                amount,
+                currency,
+                amount_text,
+                msg_text: get_text(&renderer.message),
+                sticker: None,
+                header_color: renderer.header_background_color,
+                body_color: renderer.body_background_color,
+            }
+        }
+        ChatItem::LiveChatPaidStickerRenderer(renderer) => {
+            let amount_text = get_text(&renderer.purchase_amount_text).unwrap_or_default();
+            let (amount, currency) = parse_amount(&amount_text);
+            let sticker = renderer
+                .sticker
+                .as_ref()
+                .and_then(|v| v.accessibility.as_ref())
+                .map(|v| v.accessibility_data.label.clone());
+            NoticeKind::Paid {
+                msg_id: base.id.clone(),
+                author: author.name,
+                amount,
+                currency,
+                amount_text,
+                msg_text: None,
+                sticker: sticker.or(Some("paid".to_string())),
+                header_color: renderer.money_chip_background_color,
+                body_color: renderer.background_color,
+            }
+        }
+        ChatItem::LiveChatMembershipItemRenderer(renderer) => {
+            // new members get "Welcome to {tier}!" as the subtext,
+            // milestones get "Member for {n} months" as the primary text and the tier as the subtext
+            let primary_text = get_text(&renderer.header_primary_text);
+            let subtext = get_text(&renderer.header_subtext);
+            let tier = subtext.map(|subtext| {
+                subtext
+                    .trim_start_matches("Welcome to ")
+                    .trim_end_matches('!')
+                    .to_string()
+            });
+            let months = primary_text.as_deref().and_then(parse_first_number);
+            NoticeKind::Subscription {
+                username: author.name,
+                months,
+                tier,
+                msg_text: get_text(&renderer.message),
+            }
+        }
+        ChatItem::LiveChatSponsorshipsGiftPurchaseAnnouncementRenderer(renderer) => {
+            let header = &renderer.header.live_chat_sponsorships_header_renderer;
+            // "Gifted 5 {channel} memberships"
+            let count = get_text(&header.primary_text)
+                .as_deref()
+                .and_then(parse_first_number)
+                .unwrap_or(1);
+            NoticeKind::GiftedSubs {
+                gifter: get_text(&header.author_name).unwrap_or_default(),
+                count,
+                recipients: vec![],
+            }
+        }
+        ChatItem::Unknown(_) => return None,
+    };
 
-        let chat_msg = ChatMsg {
-            author: author.to_string(),
-            location: crate::types::ChatLocation::YouTube,
-            msg_text: message,
+    Some(
+        ChatNotice {
+            location: ChatLocation::YouTube,
+            channel: channel.to_string(),
+            timestamp,
+            stream_offset: None,
+            kind,
             raw_full_msg,
-            timestamp: dt,
+        }
+        .into(),
+    )
+}
+
+/// get_author() reads the roles from the builtin badges,
+/// member badges have a custom thumbnail and the tier in the tooltip
+fn get_author(base: &ChatItemBase) -> Author {
+    let mut author = Author {
+        id: base.author_external_channel_id.clone(),
+        name: get_text(&base.author_name).unwrap_or_default(),
+        avatar_url: base
+            .author_photo
+            .as_ref()
+            .and_then(Thumbnails::largest_url)
+            .map(|url| url.to_string()),
+        ..Author::default()
+    };
+    for badge in &base.author_badges {
+        let badge = &badge.live_chat_author_badge_renderer;
+        let role = match badge.icon.as_ref().map(|icon| &icon.icon_type) {
+            Some(IconType::Owner) => Some(Role::Broadcaster),
+            Some(IconType::Moderator) => Some(Role::Moderator),
+            Some(IconType::Verified) => Some(Role::Verified),
+            Some(IconType::Unknown) => None,
+            None => badge.custom_thumbnail.is_some().then_some(Role::Subscriber),
+        };
+        if let Some(role) = role {
+            author.add_role(role);
+        }
+        author.badges.push(Badge {
+            name: badge.tooltip.clone().unwrap_or_default(),
+            image_url: badge
+                .custom_thumbnail
+                .as_ref()
+                .and_then(Thumbnails::largest_url)
+                .map(|url| url.to_string()),
+        });
+    }
+    author
+}
+
+fn get_text(text: &Option<Text>) -> Option<String> {
+    text.as_ref().map(Text::plain_text)
+}
+
+/// get_fragments() turns the runs of a text into fragments,
+/// custom emojis become emotes and the text runs are searched for mentions and links
+fn get_fragments(text: &Option<Text>) -> Vec<Fragment> {
+    let Some(text) = text else {
+        return vec![];
+    };
+    if let Some(simple_text) = &text.simple_text {
+        return Fragment::parse_text(simple_text);
+    }
+
+    let mut fragments = vec![];
+    for run in &text.runs {
+        if let Some(emoji) = &run.emoji {
+            let image_url = emoji.image.as_ref().and_then(Thumbnails::largest_url);
+            let shortcut = emoji.shortcuts.first();
+            match (emoji.is_custom_emoji, image_url, &emoji.emoji_id) {
+                (true, Some(url), id) => fragments.push(Fragment::Emote {
+                    id: id.clone().unwrap_or_default(),
+                    name: shortcut
+                        .map(|shortcut| shortcut.trim_matches(':').to_string())
+                        .unwrap_or_default(),
+                    url: url.to_string(),
+                }),
+                (false, _, Some(emoji)) => fragments.push(Fragment::Emoji {
+                    emoji: emoji.clone(),
+                    name: shortcut.cloned(),
+                }),
+                _ => fragments.push(Fragment::Text(shortcut.cloned().unwrap_or_default())),
+            }
+            continue;
+        }
+
+        let Some(text) = &run.text else {
+            continue;
         };
-        tx.send(chat_msg).unwrap();
+        let url = run
+            .navigation_endpoint
+            .as_ref()
+            .and_then(|endpoint| endpoint.url_endpoint.as_ref())
+            .map(|endpoint| endpoint.url.as_str())
+            .filter(|url| url.starts_with("https://") || url.starts_with("http://"));
+        match url {
+            Some(url) => fragments.push(Fragment::Link {
+                url: url.to_string(),
+                text: text.clone(),
+            }),
+            None => fragments.extend(Fragment::parse_text(text)),
+        }
     }
+    fragments
+}
 
-    continuation.as_array().unwrap()[0]
-        .as_str()
-        .unwrap()
-        .to_string()
+fn get_timestamp(base: &ChatItemBase) -> Option<DateTime<Utc>> {
+    let timestamp_usec: i64 = base.timestamp_usec.as_deref()?.parse().ok()?;
+
+    let timestamp = NaiveDateTime::from_timestamp_micros(timestamp_usec)?;
+
+    Some(DateTime::from_naive_utc_and_offset(timestamp, Utc))
+}
+
+/// parse_amount() splits an amount like `CA$1,000.50` or `2,00 €` into the number and the currency
+fn parse_amount(amount_text: &str) -> (f64, String) {
+    let currency: String = amount_text
+        .chars()
+        .filter(|c| !c.is_ascii_digit() && *c != '.' && *c != ',' && !c.is_whitespace())
+        .collect();
+    let number: String = amount_text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+        .collect();
+    let digits = |text: &str| {
+        text.chars()
+            .filter(char::is_ascii_digit)
+            .collect::<String>()
+    };
+
+    // the last separator is a decimal point if at most two digits follow it
+    let amount = match number.rfind(['.', ',']) {
+        Some(separator) if number.len() - separator - 1 <= 2 => format!(
+            "{}.{}",
+            digits(&number[..separator]),
+            digits(&number[separator + 1..])
+        ),
+        _ => digits(&number),
+    };
+    (amount.parse().unwrap_or_default(), currency)
+}
+
+fn parse_first_number(text: &str) -> Option<u32> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .find(|part| !part.is_empty())?
+        .parse()
+        .ok()
 }
 
 #[derive(Serialize)]
 struct GetLiveMsgReq {
-    context: Context,
+    context: Value,
     continuation: String,
 }
 
+/// the client version sent when the page didn't say, only used with the made up context below
+const FALLBACK_CLIENT_VERSION: &str = "2.20240111.00.00";
+
 impl GetLiveMsgReq {
-    fn new(continuation: String) -> Self {
-        let client = Client{
-            hl: "en".to_string(),
-            gl: "CA".to_string(),
-            device_make: "".to_string(),
-            device_model: "".to_string(),
-            user_agent: "Mozilla/5.0 (Windows NT 10.0; rv:121.0) Gecko/20100101 Firefox/121.0,gzip(gfe)".to_string(),
-            client_name: "WEB".to_string(),
-            client_version: "2.20240111.00.00".to_string(),
-            os_name: "Windows".to_string(),
-            os_version: "10.0".to_string(),
-            platform: "DESKTOP".to_string(),
-            client_form_factor: "UNKNOWN_FORM_FACTOR".to_string(),
-            time_zone: "UTC".to_string(),
-            browser_name: "Firefox".to_string(),
-            browser_version: "121.0".to_string(),
-            accept_header: "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8".to_string(),
-            screen_width_points: 425,
-            screen_height_points: 550,
-            screen_pixel_density: 1,
-            screen_density_float: 1,
-            utc_offset_minutes: 0,
-            user_interface_theme: "USER_INTERFACE_THEME_LIGHT".to_string()
-        };
+    /// new() sends the innertube context of the page the chat was found on,
+    /// so the requests look like the ones the page itself makes
+    fn new(ytcfg: &YtCfg, continuation: String) -> Self {
+        let context = ytcfg
+            .innertube_context
+            .clone()
+            .unwrap_or_else(|| fallback_context(ytcfg.client_version.as_deref()));
         Self {
             continuation,
-            context: Context { client },
+            context,
         }
     }
 }
 
+/// fallback_context() makes up an innertube context for pages without `INNERTUBE_CONTEXT`
+fn fallback_context(client_version: Option<&str>) -> Value {
+    let client = Client {
+        hl: "en".to_string(),
+        gl: "CA".to_string(),
+        device_make: "".to_string(),
+        device_model: "".to_string(),
+        user_agent:
+            "Mozilla/5.0 (Windows NT 10.0; rv:121.0) Gecko/20100101 Firefox/121.0,gzip(gfe)"
+                .to_string(),
+        client_name: "WEB".to_string(),
+        client_version: client_version
+            .unwrap_or(FALLBACK_CLIENT_VERSION)
+            .to_string(),
+        os_name: "Windows".to_string(),
+        os_version: "10.0".to_string(),
+        platform: "DESKTOP".to_string(),
+        client_form_factor: "UNKNOWN_FORM_FACTOR".to_string(),
+        time_zone: "UTC".to_string(),
+        browser_name: "Firefox".to_string(),
+        browser_version: "121.0".to_string(),
+        accept_header:
+            "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8"
+                .to_string(),
+        screen_width_points: 425,
+        screen_height_points: 550,
+        screen_pixel_density: 1,
+        screen_density_float: 1,
+        utc_offset_minutes: 0,
+        user_interface_theme: "USER_INTERFACE_THEME_LIGHT".to_string(),
+    };
+    serde_json::to_value(Context { client }).unwrap()
+}
+
 #[derive(Serialize)]
 struct Context {
     client: Client,
@@ -211,7 +876,7 @@ struct Client {
     user_interface_theme: String,
 }
 
-fn get_headers() -> HeaderMap {
+fn get_headers(ytcfg: &YtCfg) -> HeaderMap {
     let mut headers = header::HeaderMap::new();
     headers.insert("Accept", "*/*".parse().unwrap());
     headers.insert("Accept-Encoding", "gzip, deflate, br".parse().unwrap());
@@ -235,5 +900,360 @@ fn get_headers() -> HeaderMap {
     );
     headers.insert("X-Youtube-Bootstrap-Logged-In", "false".parse().unwrap());
     headers.insert("X-Youtube-Client-Name", "1".parse().unwrap());
+    if let Some(client_version) = ytcfg.client_version.as_deref().and_then(|v| v.parse().ok()) {
+        headers.insert("X-Youtube-Client-Version", client_version);
+    }
     headers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_LIVE_CHAT: &str = include_str!("../../tests/fixtures/get_live_chat.json");
+    const GET_LIVE_CHAT_REPLAY: &str =
+        include_str!("../../tests/fixtures/get_live_chat_replay.json");
+
+    /// chat_items() returns the items of the `addChatItemAction`s of a response
+    fn chat_items(response: &str) -> Vec<ChatItem> {
+        let response: GetLiveChatResponse = serde_json::from_str(response).unwrap();
+        response
+            .continuation_contents
+            .and_then(|v| v.live_chat_continuation)
+            .unwrap()
+            .actions
+            .into_iter()
+            .filter_map(|action| match action {
+                Action::AddChatItemAction { item } => Some(item),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn notice_kind(item: &ChatItem) -> NoticeKind {
+        match parse_chat_item(item, "channel") {
+            Some(ChatEvent::Notice(notice)) => notice.kind,
+            other => panic!("expected a notice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn paid_message() {
+        let items = chat_items(GET_LIVE_CHAT);
+
+        assert!(matches!(
+            notice_kind(&items[1]),
+            NoticeKind::Paid {
+                msg_id: Some(msg_id),
+                author,
+                amount_text,
+                amount,
+                currency,
+                msg_text: Some(msg_text),
+                sticker: None,
+                header_color: Some(4278239141),
+                body_color: Some(4280150454),
+            } if msg_id == "ChwKGkNPMnZ1T1NPbTRJREZlTVgtd29kR0JjT3hR" && author == "Big Spender" && amount_text == "$20.00" && amount == 20.0
+                && currency == "$" && msg_text == "love the stream"
+        ));
+    }
+
+    #[test]
+    fn membership_and_gift() {
+        let items = chat_items(GET_LIVE_CHAT);
+
+        assert!(matches!(
+            notice_kind(&items[2]),
+            NoticeKind::Subscription { username, months: None, tier: Some(tier), msg_text: None }
+                if username == "New Member" && tier == "Some Channel"
+        ));
+        assert!(matches!(
+            notice_kind(&items[3]),
+            NoticeKind::GiftedSubs { gifter, count: 5, recipients }
+                if gifter == "Generous Gifter" && recipients.is_empty()
+        ));
+        // viewer engagement messages aren't shown
+        assert!(parse_chat_item(&items[4], "channel").is_none());
+    }
+
+    #[test]
+    fn amounts() {
+        let cases = [
+            ("$20.00", 20.0, "$"),
+            ("CA$1,000.50", 1000.5, "CA$"),
+            ("2,00 €", 2.0, "€"),
+            ("1.000 ₫", 1000.0, "₫"),
+            ("¥1,500", 1500.0, "¥"),
+            ("free", 0.0, "free"),
+        ];
+        for (amount_text, amount, currency) in cases {
+            assert_eq!(
+                parse_amount(amount_text),
+                (amount, currency.to_string()),
+                "{}",
+                amount_text
+            );
+        }
+    }
+
+    #[test]
+    fn first_number() {
+        assert_eq!(parse_first_number("Member for 12 months"), Some(12));
+        assert_eq!(parse_first_number("Gifted 5 memberships"), Some(5));
+        assert_eq!(parse_first_number("no digits"), None);
+    }
+
+    fn action(value: Value) -> Action {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn text_item(id: &str, text: &str) -> Value {
+        serde_json::json!({
+            "liveChatTextMessageRenderer": {
+                "message": { "runs": [{ "text": text }] },
+                "authorName": { "simpleText": "Some Viewer" },
+                "id": id,
+                "timestampUsec": "1700650336123456",
+                "authorExternalChannelId": "UCxxxxxxxxxxxxxxxxxxxxxx"
+            }
+        })
+    }
+
+    fn deleted_ids(chat_events: &[ChatEvent]) -> Vec<&str> {
+        chat_events
+            .iter()
+            .filter_map(|chat_event| match chat_event {
+                ChatEvent::Notice(notice) => match &notice.kind {
+                    NoticeKind::MsgDeleted { msg_id } => Some(msg_id.as_str()),
+                    _ => None,
+                },
+                ChatEvent::Msg(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn author_msgs() {
+        let mut author_msgs = AuthorMsgs::default();
+        for (id, text) in [("a", "first"), ("b", "second")] {
+            let added = action(serde_json::json!({
+                "addChatItemAction": { "item": text_item(id, text) }
+            }));
+            assert_eq!(parse_action(&added, "channel", &mut author_msgs).len(), 1);
+        }
+
+        let replaced = action(serde_json::json!({
+            "replaceChatItemAction": {
+                "targetItemId": "b",
+                "replacementItem": text_item("c", "second, edited")
+            }
+        }));
+        let chat_events = parse_action(&replaced, "channel", &mut author_msgs);
+        assert!(matches!(
+            &chat_events[..],
+            [ChatEvent::Notice(ChatNotice { kind: NoticeKind::MsgReplaced { msg_id, msg }, .. })]
+                if msg_id == "b" && msg.id.as_deref() == Some("c")
+        ));
+
+        let deleted = action(serde_json::json!({
+            "markChatItemAsDeletedAction": { "targetItemId": "a" }
+        }));
+        let chat_events = parse_action(&deleted, "channel", &mut author_msgs);
+        assert_eq!(deleted_ids(&chat_events), ["a"]);
+
+        // the replaced message is forgotten, its replacement is removed with the author
+        let removed = action(serde_json::json!({
+            "markChatItemsByAuthorAsDeletedAction": {
+                "externalChannelId": "UCxxxxxxxxxxxxxxxxxxxxxx"
+            }
+        }));
+        let chat_events = parse_action(&removed, "channel", &mut author_msgs);
+        assert_eq!(deleted_ids(&chat_events), ["a", "c"]);
+        assert!(matches!(
+            chat_events.last(),
+            Some(ChatEvent::Notice(ChatNotice { kind: NoticeKind::AuthorRemoved { author }, .. }))
+                if author == "Some Viewer"
+        ));
+
+        // an author removed twice, or never seen, only gets the removal notice
+        let chat_events = parse_action(&removed, "channel", &mut author_msgs);
+        assert!(deleted_ids(&chat_events).is_empty());
+        assert_eq!(chat_events.len(), 1);
+    }
+
+    #[test]
+    fn fragments() {
+        let items = chat_items(GET_LIVE_CHAT);
+        let ChatItem::LiveChatTextMessageRenderer(renderer) = &items[0] else {
+            panic!("expected a text message, got {:?}", items[0]);
+        };
+        assert_eq!(
+            get_fragments(&renderer.message),
+            [
+                Fragment::Text("hello chat ".to_string()),
+                Fragment::Emote {
+                    id: "UCkwoOPPLW0uDMlVd_3e4Vhw/xKpNZYKlEt-X8wT7mLmYCg".to_string(),
+                    name: "yt".to_string(),
+                    url: "https://yt3.ggpht.com/emoji-48".to_string(),
+                },
+                Fragment::Text(" ".to_string()),
+                Fragment::Emoji {
+                    emoji: "😀".to_string(),
+                    name: Some(":grinning_face:".to_string()),
+                },
+                Fragment::Text(" see ".to_string()),
+                Fragment::Link {
+                    url: "https://www.youtube.com/redirect?q=https%3A%2F%2Fexample.com%2F"
+                        .to_string(),
+                    text: "example.com".to_string(),
+                },
+            ]
+        );
+
+        // plain text runs are searched for links and mentions
+        let text: Option<Text> = serde_json::from_value(serde_json::json!({
+            "runs": [{ "text": "@bob: see https://example.com). @ a@b" }]
+        }))
+        .unwrap();
+        assert_eq!(
+            get_fragments(&text),
+            [
+                Fragment::Mention("bob".to_string()),
+                Fragment::Text(": see ".to_string()),
+                Fragment::Link {
+                    url: "https://example.com".to_string(),
+                    text: "https://example.com".to_string(),
+                },
+                Fragment::Text("). @ a@b".to_string()),
+            ]
+        );
+    }
+
+    fn continuation(value: Value) -> Continuation {
+        Continuation::parse(vec![serde_json::from_value(value).unwrap()])
+    }
+
+    #[test]
+    fn continuations() {
+        let timed = |timeout: Duration| Continuation::Timed {
+            token: "next".to_string(),
+            timeout,
+        };
+        let cases = [
+            (
+                serde_json::json!({ "timedContinuationData": { "continuation": "next", "timeoutMs": 5000 } }),
+                timed(Duration::from_secs(5)),
+            ),
+            (
+                serde_json::json!({ "invalidationContinuationData": { "continuation": "next", "timeoutMs": 10000 } }),
+                timed(Duration::from_secs(10)),
+            ),
+            (
+                serde_json::json!({ "invalidationContinuationData": { "continuation": "next" } }),
+                timed(DEFAULT_POLL_INTERVAL),
+            ),
+            (
+                serde_json::json!({ "timedContinuationData": { "continuation": "next", "timeoutMs": 0 } }),
+                timed(MIN_POLL_INTERVAL),
+            ),
+            (
+                serde_json::json!({ "timedContinuationData": { "continuation": "next", "timeoutMs": 3600000 } }),
+                timed(MAX_POLL_INTERVAL),
+            ),
+            (
+                serde_json::json!({ "reloadContinuationData": { "continuation": "next" } }),
+                Continuation::Reload {
+                    token: "next".to_string(),
+                },
+            ),
+            (
+                serde_json::json!({ "playerSeekContinuationData": { "continuation": "next" } }),
+                Continuation::Ended,
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(continuation(value.clone()), expected, "{}", value);
+        }
+        assert_eq!(Continuation::parse(vec![]), Continuation::Ended);
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let mut author_msgs = AuthorMsgs::default();
+        let next = parse_replay_msg(
+            &tx,
+            "video",
+            &mut author_msgs,
+            GET_LIVE_CHAT_REPLAY.to_string(),
+        )
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut offsets = vec![];
+        while let Some(chat_event) = rx.recv().await {
+            let stream_offset = match &chat_event {
+                ChatEvent::Msg(msg) => msg.stream_offset,
+                ChatEvent::Notice(notice) => notice.stream_offset,
+            };
+            offsets.push(stream_offset.map(|offset| offset.as_millis()));
+        }
+        // the placeholder isn't shown, and the sticker of the removed author isn't a message,
+        // so only the removal is sent at 21500
+        assert_eq!(offsets, [Some(3021), Some(15012), Some(21500)]);
+
+        // the cursor is shared by the clones kept across reconnects
+        let next = next.unwrap();
+        assert!(next.starts_with("op2w0wR2"));
+        let replay_cursor = ReplayCursor::default();
+        assert_eq!(replay_cursor.get(), None);
+        replay_cursor.clone().set(next.clone());
+        assert_eq!(replay_cursor.get(), Some(next));
+
+        // only a missing continuation ends the replay, an error payload doesn't
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let last_page = r#"{"continuationContents": {"liveChatContinuation": {"actions": []}}}"#;
+        let next = parse_replay_msg(&tx, "video", &mut author_msgs, last_page.to_string()).await;
+        assert!(matches!(next, Ok(None)));
+        let error = r#"{"error": {"code": 500}}"#;
+        let next = parse_replay_msg(&tx, "video", &mut author_msgs, error.to_string()).await;
+        assert!(next.is_err());
+    }
+
+    #[test]
+    fn author_roles() {
+        let items = chat_items(GET_LIVE_CHAT);
+        let author = get_author(items[0].base().unwrap());
+
+        assert_eq!(author.id.as_deref(), Some("UCxxxxxxxxxxxxxxxxxxxxxx"));
+        assert_eq!(author.name, "Some Viewer");
+        assert_eq!(
+            author.avatar_url.as_deref(),
+            Some("https://yt4.ggpht.com/photo-64")
+        );
+        assert_eq!(
+            author.roles,
+            [Role::Moderator, Role::Subscriber, Role::Verified]
+        );
+        assert_eq!(
+            author.badges[1],
+            Badge {
+                name: "Member (6 months)".to_string(),
+                image_url: Some("https://yt3.ggpht.com/badge-32".to_string()),
+            }
+        );
+        assert_eq!(author.badges[2].image_url, None);
+
+        let owner = serde_json::json!({
+            "authorName": { "simpleText": "Some Channel" },
+            "authorBadges": [
+                { "liveChatAuthorBadgeRenderer": { "icon": { "iconType": "OWNER" }, "tooltip": "Owner" } },
+                { "liveChatAuthorBadgeRenderer": { "icon": { "iconType": "YOUTUBE_ROUND" } } }
+            ]
+        });
+        let owner = get_author(&serde_json::from_value(owner).unwrap());
+        assert_eq!(owner.roles, [Role::Broadcaster]);
+        assert_eq!(owner.badges.len(), 2);
+    }
+}